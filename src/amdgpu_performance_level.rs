@@ -10,7 +10,7 @@ pub trait AmdGpuSysfsPerformanceLevel {
     fn performance_level_file(&self) -> &'static str;
 }
 
-static PERFORMANCE_LEVEL_TO_STRING: [(PerformanceLevel, &'static str); 8] = [
+static PERFORMANCE_LEVEL_TO_STRING: [(PerformanceLevel, &str); 8] = [
         (PerformanceLevel::Auto, "auto"),
         (PerformanceLevel::Low, "low"),
         (PerformanceLevel::High, "high"),
@@ -28,16 +28,20 @@ impl<T: AmdGpuSysfsPerformanceLevel + SysfsDevice> ControllablePerformanceLevel
         let data = sysfs::read_string_from_file(&path).trim().to_string();
 
         PERFORMANCE_LEVEL_TO_STRING.iter()
-            .filter(|(_, name)| data.eq_ignore_ascii_case(name))
-            .next().expect("Invalid performance level").0.clone()
+            .find(|(_, name)| data.eq_ignore_ascii_case(name))
+            .expect("Invalid performance level").0.clone()
     }
 
     fn set_performance_level(&self, level: PerformanceLevel) {
+        self.try_set_performance_level(level).expect("Failed to write file");
+    }
+
+    fn try_set_performance_level(&self, level: PerformanceLevel) -> Result<(), std::io::Error> {
         let path: PathBuf = self.sysfs_dir().join(self.performance_level_file());
         let value: &'static str = PERFORMANCE_LEVEL_TO_STRING.iter()
-            .filter(|(i_level, _)| i_level.eq(&level))
-            .next().expect("Invalid performance level").1;
+            .find(|(i_level, _)| i_level.eq(&level))
+            .expect("Invalid performance level").1;
 
-        sysfs::write(path, value);
+        sysfs::try_write(path, value)
     }
 }
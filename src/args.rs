@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub sysfs_root: Option<PathBuf>,
+    pub render_node: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub print_defaults: bool,
+    pub caps: bool,
+    pub check_config: Option<PathBuf>,
+    pub guard_table: Option<PathBuf>,
+    pub save_state: Option<PathBuf>,
+    pub restore_state: Option<PathBuf>,
+    pub fan_curves: Option<PathBuf>,
+    pub monitor: bool,
+    pub read_only: bool,
+    pub db: Option<PathBuf>
+}
+
+impl Args {
+    pub fn parse<I: Iterator<Item=String>>(mut args: I) -> Result<Args, String> {
+        let mut parsed = Args::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let path = args.next().ok_or("--config requires a path")?;
+                    parsed.config = Some(PathBuf::from(path));
+                },
+                "--profile" => {
+                    let name = args.next().ok_or("--profile requires a name")?;
+                    parsed.profile = Some(name);
+                },
+                "--sysfs-root" => {
+                    let path = args.next().ok_or("--sysfs-root requires a path")?;
+                    parsed.sysfs_root = Some(PathBuf::from(path));
+                },
+                "--render-node" => {
+                    let path = args.next().ok_or("--render-node requires a path")?;
+                    parsed.render_node = Some(PathBuf::from(path));
+                },
+                "--print-defaults" => parsed.print_defaults = true,
+                "--caps" => parsed.caps = true,
+                "--monitor" => parsed.monitor = true,
+                "--read-only" => parsed.read_only = true,
+                "--db" => {
+                    let path = args.next().ok_or("--db requires a path")?;
+                    parsed.db = Some(PathBuf::from(path));
+                },
+                "--check-config" => {
+                    let path = args.next().ok_or("--check-config requires a path")?;
+                    parsed.check_config = Some(PathBuf::from(path));
+                },
+                "--guard-table" => {
+                    let path = args.next().ok_or("--guard-table requires a path")?;
+                    parsed.guard_table = Some(PathBuf::from(path));
+                },
+                "--save-state" => {
+                    let path = args.next().ok_or("--save-state requires a path")?;
+                    parsed.save_state = Some(PathBuf::from(path));
+                },
+                "--restore-state" => {
+                    let path = args.next().ok_or("--restore-state requires a path")?;
+                    parsed.restore_state = Some(PathBuf::from(path));
+                },
+                "--fan-curves" => {
+                    let path = args.next().ok_or("--fan-curves requires a path")?;
+                    parsed.fan_curves = Some(PathBuf::from(path));
+                },
+                _ => return Err(format!("Unknown argument {}", arg))
+            }
+        }
+
+        Ok(parsed)
+    }
+}
@@ -0,0 +1,51 @@
+use crate::clamped_percentage::ClampedPercentage;
+use crate::fan::FanMode;
+use crate::performance_level::PerformanceLevel;
+use crate::polaris_gpu::{OverclockError, PcieLevel, PowerLimitError};
+
+use serde::{Deserialize, Serialize};
+
+/**
+    Everything the daemon controls on the card, as read at one point in time.
+    Settings the card doesn't expose are None and are left alone on restore.
+**/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CardState {
+    /// The pp_od_clk_voltage table as the driver printed it
+    pub pstate_table: Option<String>,
+    pub power_limit: Option<f32>,
+    pub performance_level: Option<PerformanceLevel>,
+    pub fan_mode: Option<FanMode>,
+    /// Only restored with a manual fan_mode, the firmware drives it otherwise
+    pub fan_speed: Option<ClampedPercentage>,
+    pub pcie_level: Option<PcieLevel>,
+    /// Index into pp_power_profile_mode
+    pub power_profile_mode: Option<u8>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RestoreError {
+    /// The saved table isn't in a format the parser understands
+    UnparsedTable,
+    Pstates(OverclockError),
+    PowerLimit(PowerLimitError),
+    PerformanceLevel(std::io::ErrorKind),
+    /// Also what the driver answers when the restored performance level isn't manual
+    PowerProfile(std::io::ErrorKind),
+    PcieLevel(std::io::ErrorKind)
+}
+
+impl std::fmt::Display for RestoreError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RestoreError::UnparsedTable => write!(f, "The saved pstate table couldn't be parsed"),
+            RestoreError::Pstates(err) => write!(f, "Failed to restore the pstate table: {:?}", err),
+            RestoreError::PowerLimit(err) => write!(f, "Failed to restore the power limit: {:?}", err),
+            RestoreError::PerformanceLevel(err) => write!(f, "Failed to restore the performance level: {:?}", err),
+            RestoreError::PowerProfile(err) => write!(f, "Failed to restore the power profile mode: {:?}", err),
+            RestoreError::PcieLevel(err) => write!(f, "Failed to restore the PCIe level: {:?}", err)
+        }
+    }
+
+}
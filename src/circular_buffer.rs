@@ -10,7 +10,7 @@ impl<T> CircularBuffer<T> {
     pub fn new(size: usize) -> CircularBuffer<T> {
         CircularBuffer { 
             data: Vec::with_capacity(size),
-            size: size,
+            size,
             last: 0
         }
     }
@@ -30,20 +30,33 @@ impl<T> CircularBuffer<T> {
         self.data.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.size
+    }
+
     pub fn iter(&self) -> CircularIterator<'_, T> {
         let len = self.data.len();
 
         CircularIterator {
             buffer: &self.data,
             cur: self.last,
-            rev_cur: if self.last == 0 { len - 1 } else { self.last - 1 },
+            // Until the buffer is full last stays 0 and the newest value is at the end, an empty one has none
+            rev_cur: if self.last == 0 { len.saturating_sub(1) } else { self.last - 1 },
             left: len
         }
     }
 
-    #[allow(dead_code)]
+    /// Most recently added value, `last` is where the next one goes once the buffer is full
     pub fn last(&self) -> &T {
-        &self.data[self.last]
+        if self.data.len() < self.size || self.last == 0 {
+            &self.data[self.data.len() - 1]
+        } else {
+            &self.data[self.last - 1]
+        }
     }
 
 }
@@ -149,6 +162,31 @@ mod tests {
         assert_eq!(it.next(), Some(&7f64));
     }
 
+    #[test]
+    fn reverse_it_non_full() {
+
+        let mut buffer = CircularBuffer::<f64>::new(5);
+
+        buffer.add(1);
+        buffer.add(2);
+        buffer.add(3);
+
+        let mut it = buffer.iter().rev();
+
+        assert_eq!(it.next(), Some(&3f64));
+        assert_eq!(it.next(), Some(&2f64));
+        assert_eq!(it.next(), Some(&1f64));
+        assert_eq!(it.next(), None);
+    }
+
+    #[test]
+    fn reverse_it_empty() {
+
+        let buffer = CircularBuffer::<f64>::new(5);
+
+        assert_eq!(buffer.iter().next_back(), None);
+    }
+
     #[test]
     fn reverse_it_overflown() {
 
@@ -171,4 +209,30 @@ mod tests {
         assert_eq!(it.next(), Some(&4f64));
         assert_eq!(it.next(), Some(&3f64));
     }
+
+    fn filled(count: usize) -> CircularBuffer<f64> {
+        let mut buffer = CircularBuffer::<f64>::new(3);
+        for value in 1..=count {
+            buffer.add(value as f64);
+        }
+        buffer
+    }
+
+    #[test]
+    fn iterates_exactly_full_buffer() {
+        let buffer = filled(3);
+
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![1f64, 2f64, 3f64]);
+        assert_eq!(buffer.iter().rev().copied().collect::<Vec<_>>(), vec![3f64, 2f64, 1f64]);
+        assert_eq!(*buffer.last(), 3f64);
+    }
+
+    #[test]
+    fn iterates_after_first_overwrite() {
+        let buffer = filled(4);
+
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![2f64, 3f64, 4f64]);
+        assert_eq!(buffer.iter().rev().copied().collect::<Vec<_>>(), vec![4f64, 3f64, 2f64]);
+        assert_eq!(*buffer.last(), 4f64);
+    }
 }
\ No newline at end of file
@@ -2,7 +2,10 @@ use std::convert::TryFrom;
 use std::convert::Into;
 use std::fmt;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "f64", into = "f64")]
 pub struct ClampedPercentage(pub f64);
 
 #[derive(Debug)]
@@ -23,6 +26,21 @@ impl fmt::Display for ClampedPercentage {
     }
 }
 
+impl fmt::Display for ClampedPercentageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClampedPercentageError::TooLittle => write!(f, "percentage must not be below 0"),
+            ClampedPercentageError::TooBig => write!(f, "percentage must not be above 100")
+        }
+    }
+}
+
+impl From<ClampedPercentage> for f64 {
+    fn from(percentage: ClampedPercentage) -> f64 {
+        percentage.0
+    }
+}
+
 impl TryFrom<f64> for ClampedPercentage {
     type Error = ClampedPercentageError;
 
@@ -0,0 +1,851 @@
+use crate::clamped_percentage::ClampedPercentage;
+use crate::curve::CurvePoint;
+use crate::performance_level::PerformanceLevel;
+use crate::polaris_gpu::PcieLevel;
+use crate::schedule::ScheduledLevel;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PerformanceDecision {
+    /// usage > saturated_usage_threshold or (usage > active_usage_threshold and power > performance_power_enter)
+    Thresholds,
+    /// Weighted blend of normalized usage and power compared to a single threshold
+    LoadScore
+}
+
+/// Limit of the live card a relative config value is a percentage of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardLimit {
+    /// Critical temperature reported by hwmon
+    Crit,
+    SclkMax,
+    MclkMax
+}
+
+/// A config value written as a percentage of a card limit, e.g. "80% crit"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CardRelative {
+    pub percent: f32,
+    pub limit: CardLimit
+}
+
+impl CardRelative {
+    pub fn parse(data: &str) -> Option<CardRelative> {
+        let (percent, limit) = data.split_once('%')?;
+        let limit = match limit.trim() {
+            "crit" => CardLimit::Crit,
+            "sclk_max" => CardLimit::SclkMax,
+            "mclk_max" => CardLimit::MclkMax,
+            _ => return None
+        };
+
+        Some(CardRelative { percent: percent.trim().parse().ok()?, limit })
+    }
+
+    fn resolve(&self, limits: &CardLimits) -> Option<f32> {
+        let limit = match self.limit {
+            CardLimit::Crit => limits.crit,
+            CardLimit::SclkMax => limits.sclk_max.map(|clock| clock as f32),
+            CardLimit::MclkMax => limits.mclk_max.map(|clock| clock as f32)
+        };
+        limit.map(|limit| limit * self.percent / 100f32)
+    }
+}
+
+/// Limits of the live card which relative config values are resolved against
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CardLimits {
+    pub crit: Option<f32>,
+    pub sclk_max: Option<u32>,
+    pub mclk_max: Option<u32>
+}
+
+/// Updates the usage buffer spans, usage_short_window has to be shorter
+pub const USAGE_WINDOW_UPDATES: usize = 20;
+/// Updates the temperature buffer spans
+pub const TEMPERATURE_WINDOW_UPDATES: usize = 10;
+/// Updates the temperature slope is taken over, enough to smooth out the 1C sensor resolution
+pub const TEMPERATURE_SLOPE_UPDATES: usize = 2;
+/// Updates the power usage buffer spans
+pub const POWER_WINDOW_UPDATES: usize = 5;
+
+/// Fields which may be given relative to the card, the clocks are whole MHz
+static RELATIVE_FIELDS: &[(&str, bool)] = &[
+    ("cooloff_enter_temperature", false),
+    ("cooloff_exit_temperature", false),
+    ("emergency_temperature", false),
+    ("mining_core_clock", true),
+    ("mining_memory_clock", true),
+    ("performance_memory_clock", true),
+    ("safe_sclk_max", true),
+    ("safe_mclk_max", true)
+];
+
+/// What is applied before the first decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StartupState {
+    Idle,
+    /// Leaves the card as it is until the state machine decides on a state
+    Auto,
+    Performance
+}
+
+/// Which temperature the Emergency cutoff is checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EmergencySensorMode {
+    /// The hottest of edge, junction and memory
+    Hottest,
+    /// Edge only, for cards with a flaky junction sensor
+    Edge,
+    /// Mean of the available sensors
+    Average
+}
+
+/// A startup precondition, see GpuStateMachine::preflight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PreflightCheck {
+    /// The sysfs nodes the control loop can't run without exist, see PolarisGpu::validate_nodes
+    Nodes,
+    /// The files the daemon writes can be opened for writing, skipped in --read-only mode
+    Writable,
+    /// The sensor emergency_sensor_mode checks can be read
+    EmergencySensor,
+    /// There is a pwm to control, without one the firmware keeps managing the fan
+    FanControl,
+    /// The power limit range can be read and holds the configured power limits
+    PowerRange
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Name shown in logs, detected from the PCI IDs when unset
+    pub gpu_name: Option<String>,
+    /// Name of the hwmon to bind to, stable across hwmonN renumbering
+    pub hwmon_name: Option<String>,
+    /// Added to the edge temperature (C) to calibrate it
+    pub edge_offset: f32,
+    /// Added to the junction temperature (C) to calibrate it
+    pub junction_offset: f32,
+    /// Added to the memory temperature (C) to calibrate it
+    pub memory_offset: f32,
+    /// Share of the junction temperature in the control temperature, the rest is edge
+    pub junction_weight: f32,
+    /// Seconds between state machine steps
+    pub update_interval: f32,
+    /// Sensor samples gathered per update interval
+    pub samples_per_update: usize,
+    /// Keeps the sensor files open and seeks back for each read instead of reopening them
+    pub keep_sysfs_files_open: bool,
+    /// How Performance is entered: "Thresholds" or a weighted "LoadScore"
+    pub performance_decision: PerformanceDecision,
+    /// LoadScore weight of gpu usage (0-1)
+    pub usage_weight: f64,
+    /// LoadScore weight of power usage normalized to the power limit range (0-1)
+    pub power_weight: f64,
+    /// LoadScore above which Performance is entered
+    pub performance_score_threshold: f64,
+    /// Usage (%) above which the gpu counts as doing something, below it Performance can be left
+    pub active_usage_threshold: f64,
+    /// Weighted usage (%) above which Idle enters Performance regardless of power
+    pub performance_usage_enter: f64,
+    /// Usage (%) above which Thresholds enters Performance from any state regardless of power
+    pub saturated_usage_threshold: f64,
+    /// Hold Performance back while only the short usage window is busy, see UsageLoad
+    pub filter_transient_load: bool,
+    /// Updates the short usage window spans, the long one is the whole usage buffer
+    pub usage_short_window: usize,
+    /// Average usage (%) over the long window above which load counts as sustained
+    pub sustained_usage_threshold: f64,
+    /// Power (W) above which Thresholds enters Performance
+    pub performance_power_enter: f32,
+    /// Average power (W) below which Performance can be left
+    pub performance_power_exit: f32,
+    /// Seconds the load has to stay low before Performance is left
+    pub performance_exit_delay: f32,
+    /// Idle temperature (C) at which the fan is spun up to cool off
+    pub cooloff_enter_temperature: f32,
+    /// Temperature (C) at which cooling off ends
+    pub cooloff_exit_temperature: f32,
+    /// CoolOff fan speed at cooloff_exit_temperature, without a cooloff_fan_curve
+    pub cooloff_fan_speed_min: ClampedPercentage,
+    /// CoolOff fan speed at cooloff_enter_temperature and above, without a cooloff_fan_curve
+    pub cooloff_fan_speed_max: ClampedPercentage,
+    /// Power limit (W) in Idle
+    pub idle_power_limit: f32,
+    /// Power limit (W) in Performance
+    pub performance_power_limit: f32,
+    /// Power limit (W) in Mining
+    pub mining_power_limit: f32,
+    /// Highest core clock (MHz) in Mining
+    pub mining_core_clock: u32,
+    /// Core voltage (mV) of the highest Mining state
+    pub mining_core_voltage: u32,
+    /// Pins memory in Mining to the state nearest this clock (MHz) instead of the highest one
+    pub mining_memory_clock: Option<u32>,
+    /// Steady fan speed in Mining without a mining_fan_curve
+    pub mining_fan_speed: ClampedPercentage,
+    /// Seconds before a power limit which didn't stick is written again
+    pub power_limit_retry_delay: f32,
+    /// Applied clocks are rounded to multiples of this (MHz)
+    pub pstate_clock_step: u32,
+    /// Applied voltages are rounded to multiples of this (mV)
+    pub pstate_voltage_step: u32,
+    /// Idles memory at the bottom of its clock range when that is below its lowest state
+    pub idle_memory_range_floor: bool,
+    /// Holds memory at its Performance clock in every state but Mining, which pins memory on its own
+    pub pin_memory_always: bool,
+    /// Forced performance level in Idle, power profiles need "manual"
+    pub idle_performance_level: PerformanceLevel,
+    /// Forced performance level in Performance
+    pub performance_performance_level: PerformanceLevel,
+    /// Forced performance level in Mining
+    pub mining_performance_level: PerformanceLevel,
+    /// Power profile mode applied in Idle, by name
+    pub idle_power_profile: Option<String>,
+    /// Power profile mode applied in Performance, by name
+    pub performance_power_profile: Option<String>,
+    /// Power profile mode applied in Mining, by name
+    pub mining_power_profile: Option<String>,
+    /// PCIe link level applied in Idle
+    pub idle_pcie_level: Option<PcieLevel>,
+    /// PCIe link level applied in Performance
+    pub performance_pcie_level: Option<PcieLevel>,
+    /// Temperature (C) forcing the safe Emergency settings
+    pub emergency_temperature: f32,
+    /// Sensor reading compared to emergency_temperature
+    pub emergency_sensor_mode: EmergencySensorMode,
+    /// Degrees (C) below emergency_temperature required to leave Emergency
+    pub emergency_recovery_margin: f32,
+    /// Seconds the temperature has to stay below the margin before Emergency is left
+    pub emergency_recovery_hold: f32,
+    /// Shell command run when Emergency is entered
+    pub on_emergency: Option<String>,
+    /// Use instantaneous power (power1_input) to enter Performance
+    pub instant_power_entry: bool,
+    /// Performance is only entered while a process with this name runs
+    pub performance_gate_process: Option<String>,
+    /// Reset the pstate table before deriving the state tables from it
+    pub reset_on_startup: bool,
+    /// Programs fan_curve into the firmware on startup as a fallback for an unclean exit
+    pub failsafe_firmware_curve: bool,
+    /// State applied on startup, "Auto" leaves the card alone until the first decision
+    pub startup_state: StartupState,
+    /// Preconditions checked before touching the card, startup is aborted when one fails
+    pub preflight_checks: Vec<PreflightCheck>,
+    /// Fan speed readings above this (RPM) are treated as tachometer glitches
+    pub fan_rpm_plausible_max: u32,
+    /// Commanded fan speed (%) above which a stopped fan counts as failed
+    pub fan_failure_min_speed: f64,
+    /// Consecutive stalled RPM samples required before the fan counts as failed
+    pub fan_failure_samples: usize,
+    /// Seconds the fan has to answer a commanded speed increase in before it's reported as lagging
+    pub fan_ramp_timeout: f32,
+    /// Deviation (%) from the learned RPM at a duty reported as fan wear
+    pub fan_baseline_deviation: f64,
+    /// Fan speed (RPM) noise_reference_dba was measured at, no noise estimate without it
+    pub noise_reference_rpm: Option<u32>,
+    /// Loudness (dBA) of the fan at noise_reference_rpm
+    pub noise_reference_dba: f32,
+    /// Seconds between table checks in --guard-table mode
+    pub guard_table_interval: f32,
+    /// Seconds between redraws in --monitor mode
+    pub display_refresh_interval: f32,
+    /// Only print the status line when the state, the whole degree or the fan speed changed
+    pub log_on_change: bool,
+    /// Fan speed change (%) which counts as a change for log_on_change
+    pub log_fan_speed_step: f64,
+    /// Temperature rise (C/s) at which the fan is ramped up ahead of the curve
+    pub max_temp_rise_rate: Option<f32>,
+    /// Measured core voltage (mV) off the table by more than this is reported
+    pub voltage_discrepancy_margin: u32,
+    /// Junction temperature (C) Performance's power limit is lowered to hold, the control temperature without a junction sensor
+    pub thermal_governor_target: Option<f32>,
+    /// Watts the power limit moves per degree off the governor target each step
+    pub thermal_governor_gain: f32,
+    /// Rows recorded by --db before they are committed
+    pub db_flush_rows: usize,
+    /// Named pipe receiving newline-delimited JSON events
+    pub event_pipe: Option<PathBuf>,
+    /// Keeps the daemon running through a panic in the loop, applying Emergency instead
+    pub catch_loop_panics: bool,
+    /// Logs every sysfs write
+    pub audit: bool,
+    /// File the audit log is appended to instead of stdout
+    pub audit_file: Option<PathBuf>,
+    /// Sysfs writes allowed per minute before fan adjustments are deferred
+    pub max_writes_per_minute: Option<u32>,
+    /// File the live pstate table is written to when it can't be parsed
+    pub unparsed_table_dump: Option<PathBuf>,
+    /// Maximum fan speed change in percentage points per second
+    pub max_fan_slew_per_sec: Option<f64>,
+    /// RPM a fan speed of 100% stands for, fan speeds are held as RPM targets when set
+    pub fan_rpm_full_speed: Option<u32>,
+    /// Duty (%) the RPM controller adds per RPM below the target on each step
+    pub fan_rpm_gain: f64,
+    /// Watts per second the power limit may rise by
+    pub power_limit_rise_rate: Option<f64>,
+    /// Watts per second the power limit may fall by
+    pub power_limit_fall_rate: Option<f64>,
+    /// Pins memory in Performance to the existing state nearest this clock (MHz)
+    pub performance_memory_clock: Option<u32>,
+    /// Highest core clock (MHz) any applied state may use, on top of the driver's range
+    pub safe_sclk_max: Option<u32>,
+    /// Highest memory clock (MHz) any applied state may use, on top of the driver's range
+    pub safe_mclk_max: Option<u32>,
+    /// Idle fan curve instead of a stopped fan
+    pub idle_fan_curve: Option<Vec<CurvePoint>>,
+    /// CoolOff fan curve instead of cooloff_fan_speed_min to cooloff_fan_speed_max
+    pub cooloff_fan_curve: Option<Vec<CurvePoint>>,
+    /// Mining fan curve instead of a fixed mining_fan_speed
+    pub mining_fan_curve: Option<Vec<CurvePoint>>,
+    /// Time windows forcing a performance level regardless of the state, except Emergency
+    pub performance_level_schedule: Option<Vec<ScheduledLevel>>,
+    /// Performance fan curve, tables have to come last in TOML
+    pub fan_curve: Vec<CurvePoint>,
+    /// Named sets of overrides switched to at runtime
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, toml::value::Table>,
+    /// Fields given relative to the card, they hold their defaults until resolve()
+    #[serde(skip)]
+    pub relative: BTreeMap<String, CardRelative>
+}
+
+/**
+    Just the fan curves of a config, the format of a --fan-curves file which
+    is reloaded whenever it changes. Curves it leaves out keep the config's.
+**/
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FanCurves {
+    pub fan_curve: Option<Vec<CurvePoint>>,
+    pub idle_fan_curve: Option<Vec<CurvePoint>>,
+    pub cooloff_fan_curve: Option<Vec<CurvePoint>>,
+    pub mining_fan_curve: Option<Vec<CurvePoint>>
+}
+
+impl FanCurves {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<FanCurves, ConfigError> {
+        let data = std::fs::read_to_string(path).map_err(|err| ConfigError::Io(err.kind()))?;
+
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &str) -> Result<FanCurves, ConfigError> {
+        toml::from_str(data).map_err(|err| ConfigError::Parse(err.to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::ErrorKind),
+    Parse(String),
+    Invalid(String)
+}
+
+impl std::fmt::Display for ConfigError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(kind) => write!(f, "Could not read config: {:?}", kind),
+            ConfigError::Parse(msg) => write!(f, "Could not parse config: {}", msg),
+            ConfigError::Invalid(msg) => write!(f, "Invalid config: {}", msg)
+        }
+    }
+
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            gpu_name: None,
+            hwmon_name: None,
+            edge_offset: 0f32,
+            junction_offset: 0f32,
+            memory_offset: 0f32,
+            junction_weight: 0f32,
+            update_interval: 1f32,
+            samples_per_update: 2,
+            keep_sysfs_files_open: false,
+            performance_decision: PerformanceDecision::Thresholds,
+            usage_weight: 0.6,
+            power_weight: 0.4,
+            performance_score_threshold: 0.6,
+            active_usage_threshold: 10f64,
+            performance_usage_enter: 90f64,
+            saturated_usage_threshold: 95f64,
+            filter_transient_load: false,
+            usage_short_window: 3,
+            sustained_usage_threshold: 50f64,
+            performance_power_enter: 45f32,
+            performance_power_exit: 35f32,
+            performance_exit_delay: 0f32,
+            cooloff_enter_temperature: 55f32,
+            cooloff_exit_temperature: 43f32,
+            cooloff_fan_speed_min: ClampedPercentage::new(20f64),
+            cooloff_fan_speed_max: ClampedPercentage::new(50f64),
+            idle_power_limit: 30f32,
+            performance_power_limit: 150f32,
+            mining_power_limit: 110f32,
+            mining_core_clock: 1000,
+            mining_core_voltage: 800,
+            mining_memory_clock: None,
+            mining_fan_speed: ClampedPercentage::new(40f64),
+            power_limit_retry_delay: 0.2,
+            pstate_clock_step: 1,
+            pstate_voltage_step: 1,
+            idle_memory_range_floor: false,
+            pin_memory_always: false,
+            idle_performance_level: PerformanceLevel::Manual,
+            performance_performance_level: PerformanceLevel::Auto,
+            mining_performance_level: PerformanceLevel::Manual,
+            idle_power_profile: Some("POWER_SAVING".to_string()),
+            performance_power_profile: None,
+            mining_power_profile: Some("COMPUTE".to_string()),
+            idle_pcie_level: None,
+            performance_pcie_level: None,
+            emergency_temperature: 90f32,
+            emergency_sensor_mode: EmergencySensorMode::Hottest,
+            emergency_recovery_margin: 5f32,
+            emergency_recovery_hold: 30f32,
+            on_emergency: None,
+            instant_power_entry: false,
+            performance_gate_process: None,
+            reset_on_startup: true,
+            failsafe_firmware_curve: false,
+            startup_state: StartupState::Idle,
+            preflight_checks: vec![PreflightCheck::Nodes, PreflightCheck::Writable, PreflightCheck::EmergencySensor, PreflightCheck::PowerRange],
+            fan_rpm_plausible_max: 7000,
+            fan_failure_min_speed: 20f64,
+            fan_failure_samples: 4,
+            fan_ramp_timeout: 5f32,
+            fan_baseline_deviation: 30f64,
+            noise_reference_rpm: None,
+            noise_reference_dba: 35f32,
+            guard_table_interval: 5f32,
+            display_refresh_interval: 0.25,
+            log_on_change: false,
+            log_fan_speed_step: 5f64,
+            max_temp_rise_rate: None,
+            voltage_discrepancy_margin: 50,
+            thermal_governor_target: None,
+            thermal_governor_gain: 1f32,
+            db_flush_rows: 30,
+            event_pipe: None,
+            catch_loop_panics: false,
+            audit: false,
+            audit_file: None,
+            max_writes_per_minute: None,
+            unparsed_table_dump: None,
+            max_fan_slew_per_sec: None,
+            fan_rpm_full_speed: None,
+            fan_rpm_gain: 0.01,
+            power_limit_rise_rate: None,
+            power_limit_fall_rate: None,
+            performance_memory_clock: None,
+            safe_sclk_max: None,
+            safe_mclk_max: None,
+            idle_fan_curve: None,
+            cooloff_fan_curve: None,
+            mining_fan_curve: None,
+            performance_level_schedule: None,
+            fan_curve: vec![
+                CurvePoint { temperature: 50, fan_speed: ClampedPercentage::new(0f64) },
+                CurvePoint { temperature: 55, fan_speed: ClampedPercentage::new(30f64) },
+                CurvePoint { temperature: 65, fan_speed: ClampedPercentage::new(35f64) },
+                CurvePoint { temperature: 75, fan_speed: ClampedPercentage::new(45f64) },
+                CurvePoint { temperature: 80, fan_speed: ClampedPercentage::new(70f64) }
+            ],
+            profiles: BTreeMap::new(),
+            relative: BTreeMap::new()
+        }
+    }
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let data = std::fs::read_to_string(path).map_err(|err| ConfigError::Io(err.kind()))?;
+
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &str) -> Result<Config, ConfigError> {
+        let mut value: toml::Value = toml::from_str(data).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        let relative = Self::take_relative(&mut value)?;
+
+        let mut config: Config = value.try_into().map_err(|err: toml::de::Error| ConfigError::Parse(err.to_string()))?;
+        config.relative = relative;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Removes the relative fields of a config table, leaving them to their defaults
+    fn take_relative(value: &mut toml::Value) -> Result<BTreeMap<String, CardRelative>, ConfigError> {
+        let mut relative = BTreeMap::new();
+        let table = match value.as_table_mut() {
+            Some(table) => table,
+            None => return Ok(relative)
+        };
+
+        for (name, _) in RELATIVE_FIELDS.iter() {
+            if let Some(data) = table.get(*name).and_then(toml::Value::as_str) {
+                let value = CardRelative::parse(data).ok_or_else(|| ConfigError::Invalid(format!(
+                    "{} has to be a number or a percentage of crit, sclk_max or mclk_max like \"80% crit\"", name)))?;
+                relative.insert(name.to_string(), value);
+                table.remove(*name);
+            }
+        }
+        Ok(relative)
+    }
+
+    /// Replaces the relative fields with values computed from the card's limits
+    pub fn resolve(&self, limits: &CardLimits) -> Result<Config, ConfigError> {
+        if self.relative.is_empty() {
+            return Ok(self.clone());
+        }
+
+        let mut value = toml::Value::try_from(Config { profiles: BTreeMap::new(), ..self.clone() })
+            .map_err(|err| ConfigError::Parse(err.to_string()))?;
+        let table = value.as_table_mut().expect("Config serializes to a table");
+
+        for (name, whole) in RELATIVE_FIELDS.iter() {
+            if let Some(relative) = self.relative.get(*name) {
+                let resolved = relative.resolve(limits).ok_or_else(|| ConfigError::Invalid(format!(
+                    "{} is relative to {:?}, which the card doesn't report", name, relative.limit)))?;
+                let resolved = if *whole {
+                    toml::Value::Integer(resolved.round() as i64)
+                } else {
+                    toml::Value::Float(resolved.into())
+                };
+                table.insert(name.to_string(), resolved);
+            }
+        }
+
+        let mut config: Config = value.try_into().map_err(|err: toml::de::Error| ConfigError::Parse(err.to_string()))?;
+        config.validate()?;
+        config.profiles = self.profiles.clone();
+        config.relative = self.relative.clone();
+
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.update_interval <= 0f32 {
+            Err(ConfigError::Invalid("update_interval must be positive".to_string()))
+        } else if self.guard_table_interval <= 0f32 {
+            Err(ConfigError::Invalid("guard_table_interval must be positive".to_string()))
+        } else if self.display_refresh_interval <= 0f32 {
+            Err(ConfigError::Invalid("display_refresh_interval must be positive".to_string()))
+        } else if self.log_fan_speed_step < 0f64 {
+            Err(ConfigError::Invalid("log_fan_speed_step can't be negative".to_string()))
+        } else if self.max_writes_per_minute == Some(0) {
+            Err(ConfigError::Invalid("max_writes_per_minute must be positive".to_string()))
+        } else if !(0f32..=1f32).contains(&self.junction_weight) {
+            Err(ConfigError::Invalid("junction_weight must be between 0 and 1".to_string()))
+        } else if self.fan_ramp_timeout <= 0f32 {
+            Err(ConfigError::Invalid("fan_ramp_timeout must be positive".to_string()))
+        } else if self.noise_reference_rpm == Some(0) {
+            Err(ConfigError::Invalid("noise_reference_rpm must be positive".to_string()))
+        } else if self.fan_rpm_full_speed == Some(0) {
+            Err(ConfigError::Invalid("fan_rpm_full_speed must be positive".to_string()))
+        } else if self.fan_rpm_gain <= 0f64 {
+            Err(ConfigError::Invalid("fan_rpm_gain must be positive".to_string()))
+        } else if self.thermal_governor_gain <= 0f32 {
+            Err(ConfigError::Invalid("thermal_governor_gain must be positive".to_string()))
+        } else if [self.power_limit_rise_rate, self.power_limit_fall_rate].iter().flatten().any(|rate| *rate <= 0f64) {
+            Err(ConfigError::Invalid("power_limit_rise_rate and power_limit_fall_rate must be positive".to_string()))
+        } else if self.max_temp_rise_rate.is_some_and(|rate| rate <= 0f32) {
+            Err(ConfigError::Invalid("max_temp_rise_rate must be positive".to_string()))
+        } else if self.fan_failure_samples == 0 {
+            Err(ConfigError::Invalid("fan_failure_samples must be at least 1".to_string()))
+        } else if self.db_flush_rows == 0 {
+            Err(ConfigError::Invalid("db_flush_rows must be at least 1".to_string()))
+        } else if self.emergency_recovery_margin < 0f32 || self.emergency_recovery_hold < 0f32 {
+            Err(ConfigError::Invalid("emergency_recovery_margin and emergency_recovery_hold can't be negative".to_string()))
+        } else if self.pstate_clock_step == 0 || self.pstate_voltage_step == 0 {
+            Err(ConfigError::Invalid("pstate_clock_step and pstate_voltage_step must be at least 1".to_string()))
+        } else if self.power_limit_retry_delay < 0f32 {
+            Err(ConfigError::Invalid("power_limit_retry_delay can't be negative".to_string()))
+        } else if self.performance_exit_delay < 0f32 {
+            Err(ConfigError::Invalid("performance_exit_delay can't be negative".to_string()))
+        } else if self.samples_per_update == 0 {
+            Err(ConfigError::Invalid("samples_per_update must be at least 1".to_string()))
+        } else if [self.active_usage_threshold, self.performance_usage_enter, self.saturated_usage_threshold,
+            self.sustained_usage_threshold].iter()
+            .any(|threshold| !(0f64..=100f64).contains(threshold)) {
+            Err(ConfigError::Invalid("Usage thresholds are percentages, they must be between 0 and 100".to_string()))
+        } else if self.active_usage_threshold >= self.performance_usage_enter ||
+            self.performance_usage_enter > self.saturated_usage_threshold {
+            Err(ConfigError::Invalid("Usage thresholds must be ordered active_usage_threshold < performance_usage_enter <= saturated_usage_threshold".to_string()))
+        } else if !(1..USAGE_WINDOW_UPDATES).contains(&self.usage_short_window) {
+            Err(ConfigError::Invalid(format!("usage_short_window must be between 1 and {} updates", USAGE_WINDOW_UPDATES - 1)))
+        } else if self.performance_power_exit >= self.performance_power_enter {
+            Err(ConfigError::Invalid("performance_power_exit must be below performance_power_enter".to_string()))
+        } else if self.cooloff_fan_speed_min.0 > self.cooloff_fan_speed_max.0 {
+            Err(ConfigError::Invalid("cooloff_fan_speed_min can't be above cooloff_fan_speed_max".to_string()))
+        } else if !self.is_relative(&["cooloff_exit_temperature", "cooloff_enter_temperature"]) &&
+            self.cooloff_exit_temperature >= self.cooloff_enter_temperature {
+            Err(ConfigError::Invalid("cooloff_exit_temperature must be below cooloff_enter_temperature".to_string()))
+        } else if !self.is_relative(&["emergency_temperature", "cooloff_enter_temperature"]) &&
+            self.emergency_temperature <= self.cooloff_enter_temperature {
+            Err(ConfigError::Invalid("emergency_temperature must be above cooloff_enter_temperature".to_string()))
+        } else if self.fan_curve.is_empty() {
+            Err(ConfigError::Invalid("fan_curve needs at least one point".to_string()))
+        } else if [&self.idle_fan_curve, &self.cooloff_fan_curve, &self.mining_fan_curve].iter()
+            .any(|curve| curve.as_ref().is_some_and(|points| points.is_empty()))
+        {
+            Err(ConfigError::Invalid("State fan curves need at least one point".to_string()))
+        } else if self.performance_level_schedule.iter().flatten().any(|window| !window.is_valid()) {
+            Err(ConfigError::Invalid("performance_level_schedule times have to be \"HH:MM\"".to_string()))
+        } else {
+            self.profiles.keys().try_for_each(|name| self.with_profile(name).map(|_| ()))
+        }
+    }
+
+    /// Relative fields hold their defaults until resolve(), so comparisons with them wait until then
+    fn is_relative(&self, fields: &[&str]) -> bool {
+        fields.iter().any(|field| self.relative.contains_key(*field))
+    }
+
+    pub fn profile_names(&self) -> Vec<&String> {
+        self.profiles.keys().collect()
+    }
+
+    /**
+        Returns this config with the fields of the named profile replaced,
+        validated on its own. The profiles are carried over so the result
+        can be switched from again.
+    **/
+    pub fn with_profile(&self, name: &str) -> Result<Config, ConfigError> {
+        let overrides = self.profiles.get(name)
+            .ok_or_else(|| ConfigError::Invalid(format!("Unknown profile {}", name)))?;
+
+        let mut base = toml::Value::try_from(Config { profiles: BTreeMap::new(), ..self.clone() })
+            .map_err(|err| ConfigError::Parse(err.to_string()))?;
+        let table = base.as_table_mut().expect("Config serializes to a table");
+        // Overridden fields drop the base's relative value, unless the override is relative itself
+        let mut relative = self.relative.clone();
+        for (key, value) in overrides.iter().filter(|(key, _)| key.as_str() != "profiles") {
+            relative.remove(key);
+            table.insert(key.clone(), value.clone());
+        }
+        relative.append(&mut Self::take_relative(&mut base)
+            .map_err(|err| ConfigError::Invalid(format!("Profile {}: {}", name, err)))?);
+
+        let mut config: Config = base.try_into()
+            .map_err(|err| ConfigError::Invalid(format!("Profile {}: {}", name, err)))?;
+        config.relative = relative;
+        config.validate()
+            .map_err(|err| ConfigError::Invalid(format!("Profile {}: {}", name, err)))?;
+        config.profiles = self.profiles.clone();
+
+        Ok(config)
+    }
+
+    /// Returns this config with the given fan curves swapped in, validated again
+    pub fn with_fan_curves(&self, curves: &FanCurves) -> Result<Config, ConfigError> {
+        let mut config = self.clone();
+        if let Some(points) = &curves.fan_curve {
+            config.fan_curve = points.clone();
+        }
+        config.idle_fan_curve = curves.idle_fan_curve.clone().or(config.idle_fan_curve);
+        config.cooloff_fan_curve = curves.cooloff_fan_curve.clone().or(config.cooloff_fan_curve);
+        config.mining_fan_curve = curves.mining_fan_curve.clone().or(config.mining_fan_curve);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// The /// comments of the serialized fields of Config, read from this file's source
+    fn field_docs() -> Vec<(&'static str, String)> {
+        let fields = include_str!("config.rs").split("pub struct Config {").nth(1)
+            .and_then(|rest| rest.split("\n}").next())
+            .unwrap_or("");
+        let mut docs = Vec::new();
+        let mut doc: Vec<&str> = Vec::new();
+        let mut skipped = false;
+
+        for line in fields.lines().map(str::trim) {
+            if let Some(text) = line.strip_prefix("///") {
+                doc.push(text.trim());
+            } else if line.starts_with("#[serde(skip)]") {
+                skipped = true;
+            } else if let Some((name, _)) = line.strip_prefix("pub ").and_then(|field| field.split_once(':')) {
+                if !skipped && !doc.is_empty() {
+                    docs.push((name, doc.join(" ")));
+                }
+                doc.clear();
+                skipped = false;
+            }
+        }
+        docs
+    }
+
+    /// Prefixes the first line of every documented field with its description
+    fn document(data: &str, docs: &[(&'static str, String)], documented: &mut Vec<&'static str>) -> String {
+        let mut out = String::new();
+
+        for line in data.lines() {
+            let key = line.trim_start_matches('[').split([' ', ']', '.']).next().unwrap_or("");
+
+            if let Some((name, doc)) = docs.iter().find(|(name, _)| *name == key) {
+                if !documented.contains(name) {
+                    documented.push(name);
+                    out.push_str(&format!("\n# {}\n", doc));
+                }
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Serializes the config with the doc comment of every field
+    pub fn to_commented_toml(&self) -> String {
+        let docs = Self::field_docs();
+        let data = toml::to_string(self).expect("Config is always serializable");
+        // Unset optional fields are plain values, so they have to go before any table
+        let (values, tables) = data.split_at(data.find("[[").unwrap_or(data.len()));
+
+        let mut documented = vec![];
+        let mut out = Self::document(values.trim_end(), &docs, &mut documented);
+        let tables = Self::document(tables, &docs, &mut documented);
+
+        for (name, doc) in docs.iter().filter(|(name, _)| !documented.contains(name)) {
+            out.push_str(&format!("\n# {}\n# {} is unset\n", doc, name));
+        }
+        out.push_str(&tables);
+
+        out.trim_start().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_config_is_default() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parses_load_score_decision() {
+        let config = Config::parse("performance_decision = \"LoadScore\"\nperformance_score_threshold = 0.5").unwrap();
+
+        assert_eq!(config.performance_decision, PerformanceDecision::LoadScore);
+        assert_eq!(config.performance_score_threshold, 0.5);
+        assert_eq!(config.usage_weight, Config::default().usage_weight);
+    }
+
+    #[test]
+    fn commented_defaults_parse_back() {
+        let data = Config::default().to_commented_toml();
+
+        assert!(data.contains("# Seconds between state machine steps\nupdate_interval = "));
+        assert!(data.contains("# max_fan_slew_per_sec is unset"));
+        assert_eq!(Config::parse(&data).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(Config::parse("update_intervall = 2.0\n").is_err());
+        assert!(Config::parse("[profiles.quiet]\nperformance_power_limt = 90.0\n").is_err());
+    }
+
+    #[test]
+    fn parses_state_fan_curves() {
+        let config = Config::parse("[[idle_fan_curve]]\ntemperature = 60\nfan_speed = 20.0\n").unwrap();
+
+        assert_eq!(config.idle_fan_curve, Some(vec![CurvePoint { temperature: 60, fan_speed: ClampedPercentage::new(20) }]));
+        assert_eq!(config.cooloff_fan_curve, None);
+        assert!(Config::parse("idle_fan_curve = []").is_err());
+    }
+
+    #[test]
+    fn swaps_in_fan_curves() {
+        let curves = FanCurves::parse("[[idle_fan_curve]]\ntemperature = 55\nfan_speed = 30.0\n").unwrap();
+        let config = Config::default().with_fan_curves(&curves).unwrap();
+
+        assert_eq!(config.idle_fan_curve, Some(vec![CurvePoint { temperature: 55, fan_speed: ClampedPercentage::new(30) }]));
+        assert_eq!(config.fan_curve, Config::default().fan_curve);
+        assert!(Config::default().with_fan_curves(&FanCurves { fan_curve: Some(vec![]), ..FanCurves::default() }).is_err());
+        assert!(FanCurves::parse("update_interval = 1.0\n").is_err());
+    }
+
+    #[test]
+    fn parses_performance_levels() {
+        let config = Config::parse("performance_performance_level = \"profile_peak\"").unwrap();
+
+        assert_eq!(config.performance_performance_level, PerformanceLevel::ProfilePeak);
+        assert!(Config::parse("idle_performance_level = \"turbo\"").is_err());
+    }
+
+    #[test]
+    fn applies_profile_overrides() {
+        let config = Config::parse("idle_power_limit = 25.0\n\
+            [profiles.silent]\n\
+            performance_power_limit = 90.0\n\
+            [[profiles.silent.fan_curve]]\n\
+            temperature = 60\n\
+            fan_speed = 40.0\n").unwrap();
+
+        let silent = config.with_profile("silent").unwrap();
+        assert_eq!(silent.idle_power_limit, 25f32);
+        assert_eq!(silent.performance_power_limit, 90f32);
+        assert_eq!(silent.fan_curve, vec![CurvePoint { temperature: 60, fan_speed: ClampedPercentage::new(40) }]);
+        assert_eq!(silent.profile_names(), vec!["silent"]);
+        assert!(config.with_profile("gaming").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_profile() {
+        assert!(Config::parse("[profiles.broken]\nsamples_per_update = 0\n").is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_fan_speed() {
+        let data = "[[fan_curve]]\ntemperature = 50\nfan_speed = 120.0\n";
+
+        assert!(Config::parse(data).is_err());
+    }
+
+    #[test]
+    fn resolves_card_relative_values() {
+        let data = "cooloff_enter_temperature = \"50% crit\"\nsafe_sclk_max = \"95% sclk_max\"\n";
+        let config = Config::parse(data).unwrap();
+        let limits = CardLimits { crit: Some(94f32), sclk_max: Some(2000), mclk_max: None };
+
+        let resolved = config.resolve(&limits).unwrap();
+        assert_eq!(resolved.cooloff_enter_temperature, 47f32);
+        assert_eq!(resolved.safe_sclk_max, Some(1900));
+        assert!(config.resolve(&CardLimits::default()).is_err());
+        assert!(Config::parse("emergency_temperature = \"hot\"\n").is_err());
+    }
+
+    #[test]
+    fn validates_around_relative_values() {
+        assert!(Config::parse("emergency_temperature = \"90% crit\"\nupdate_interval = 0.0\n").is_err());
+        // Compared against the default cooloff_enter_temperature it would be rejected
+        assert!(Config::parse("cooloff_enter_temperature = \"80% crit\"\ncooloff_exit_temperature = 70.0\n").is_ok());
+    }
+
+    #[test]
+    fn rejects_inverted_power_hysteresis() {
+        let data = "performance_power_enter = 40.0\nperformance_power_exit = 50.0\n";
+
+        assert!(Config::parse(data).is_err());
+    }
+
+    #[test]
+    fn rejects_unordered_usage_thresholds() {
+        assert!(Config::parse("active_usage_threshold = 0.5\n").is_ok());
+        assert!(Config::parse("active_usage_threshold = 95.0\n").is_err());
+        assert!(Config::parse("saturated_usage_threshold = 150.0\n").is_err());
+    }
+}
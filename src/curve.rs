@@ -0,0 +1,85 @@
+use crate::clamped_percentage::ClampedPercentage;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CurvePoint {
+    pub temperature: u32,
+    pub fan_speed: ClampedPercentage
+}
+
+#[derive(Debug, Clone)]
+pub struct Curve {
+    points: Vec<CurvePoint>
+}
+
+pub enum CurveInterpolation {
+    Linear
+}
+
+impl Curve {
+    pub fn new(points: Vec::<CurvePoint>) -> Self {
+        if points.is_empty() {
+            panic!("Invalid curve without any point");
+        }
+        let mut points_vec = points.to_vec();
+        points_vec.sort_by_key(|pt| pt.temperature);
+
+        Curve { points: points_vec }
+    }
+
+    fn interpolate(value: f32, lower: &CurvePoint, upper: &CurvePoint, interpolation: CurveInterpolation) -> ClampedPercentage {
+        if upper.temperature == lower.temperature {
+            return lower.fan_speed;
+        }
+
+        let temp_delta: f32 = (upper.temperature - lower.temperature) as f32;
+        let speed_delta: f32 = (upper.fan_speed.0 - lower.fan_speed.0) as f32;
+        let diff: f32 = value - lower.temperature as f32;
+
+        let value: f32 = match interpolation {
+            CurveInterpolation::Linear => {
+                (lower.fan_speed.0 as f32) + (diff / temp_delta) * speed_delta
+            }
+        };
+
+        ClampedPercentage::new(value)
+    }
+
+    pub fn get(&self, temperature: f32, interpolation: CurveInterpolation) -> ClampedPercentage {
+        for (idx, point) in self.points.iter().enumerate().rev() {
+            if temperature as u32 >= point.temperature {
+                // Get next point or the same if last
+                let next_point = self.points.get(idx + 1).unwrap_or(point);
+                return Self::interpolate(temperature, point, next_point, interpolation);
+            }
+        }
+
+        self.points.first().expect("Curve must have at least one point").fan_speed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(temperature: u32, fan_speed: u32) -> CurvePoint {
+        CurvePoint { temperature, fan_speed: ClampedPercentage::new(fan_speed) }
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        let curve = Curve::new(vec![point(50, 0), point(60, 50)]);
+
+        assert_eq!(curve.get(55f32, CurveInterpolation::Linear), ClampedPercentage::new(25));
+    }
+
+    #[test]
+    fn holds_edge_points_outside_the_curve() {
+        let curve = Curve::new(vec![point(60, 50), point(50, 10)]);
+
+        assert_eq!(curve.get(20f32, CurveInterpolation::Linear), ClampedPercentage::new(10));
+        assert_eq!(curve.get(60f32, CurveInterpolation::Linear), ClampedPercentage::new(50));
+        assert_eq!(curve.get(90f32, CurveInterpolation::Linear), ClampedPercentage::new(50));
+    }
+}
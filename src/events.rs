@@ -0,0 +1,64 @@
+use crate::GpuCustomState;
+
+use serde::Serialize;
+
+use std::io::prelude::*;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Transition { from: GpuCustomState, to: GpuCustomState },
+    Warning { message: String },
+    Emergency { temperature: f32 }
+}
+
+/**
+    Writes newline-delimited JSON events to a named pipe.
+    The pipe is opened non-blocking for every event, so events are dropped
+    when nobody is reading instead of stalling the control loop.
+**/
+pub struct EventPipe {
+    path: PathBuf
+}
+
+impl EventPipe {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        EventPipe { path: path.as_ref().to_path_buf() }
+    }
+
+    pub fn emit(&self, event: &Event) {
+        let mut line = match serde_json::to_string(event) {
+            Ok(line) => line,
+            Err(err) => {
+                println!("Failed to serialize {:?}: {}", event, err);
+                return;
+            }
+        };
+        line.push('\n');
+
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path);
+
+        // No reader (ENXIO) or a full pipe (EAGAIN) just drops the event
+        if let Ok(mut file) = file {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_transition_as_tagged_json() {
+        let event = Event::Transition { from: GpuCustomState::Idle, to: GpuCustomState::Performance };
+
+        assert_eq!(serde_json::to_string(&event).unwrap(),
+            "{\"event\":\"transition\",\"from\":\"Idle\",\"to\":\"Performance\"}");
+    }
+}
@@ -1,6 +1,6 @@
 use crate::clamped_percentage::ClampedPercentage;
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub enum FanMode {
     Auto,
     Manual
@@ -9,6 +9,13 @@ pub enum FanMode {
 pub trait FanControl {
     fn mode(&self) -> FanMode;
     fn set_mode(&self, mode: FanMode);
+    /// Current duty, also when the firmware is driving the fan
     fn speed(&self) -> ClampedPercentage;
+    fn try_speed(&self) -> Option<ClampedPercentage>;
     fn set_speed(&self, speed: ClampedPercentage);
+    fn rpm(&self) -> Option<u32>;
+    /// RPM the firmware aims for in auto mode, None where the driver doesn't expose it
+    fn target_rpm(&self) -> Option<u32>;
+    /// Whether the shown speed comes from the firmware rather than from us
+    fn is_firmware_controlled(&self) -> bool;
 }
@@ -0,0 +1,174 @@
+use crate::circular_buffer::CircularBuffer;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Commanded speed (percentage points) a ramp has to climb before its response is timed
+const FAN_RAMP_MIN_STEP: f64 = 10.0;
+
+/// RPM the fan has to gain for a ramp to count as answered
+const FAN_RAMP_RPM_RISE: u32 = 100;
+
+/// Samples averaged into the RPM per duty ratio of a speed band
+const FAN_BASELINE_SAMPLES: usize = 20;
+
+/// Width of the duty bands (percentage points) a baseline is learned for, the ratio isn't linear
+const FAN_BASELINE_BAND: f64 = 10.0;
+
+struct Ramp {
+    from_speed: f64,
+    from_rpm: u32,
+    /// When the commanded speed had climbed FAN_RAMP_MIN_STEP
+    reached: Option<Instant>
+}
+
+/**
+    Watches the fan for signs of wear before it stops outright: a fan which
+    doesn't speed up when told to, and a fan whose RPM at a duty drifts away
+    from what it was first measured at.
+**/
+pub struct FanHealth {
+    ramp: Option<Ramp>,
+    last: Option<(f64, u32)>,
+    band: Option<u32>,
+    ratios: CircularBuffer<f64>,
+    baselines: HashMap<u32, f64>,
+    warning: Option<String>
+}
+
+impl FanHealth {
+    pub fn new() -> Self {
+        FanHealth {
+            ramp: None,
+            last: None,
+            band: None,
+            ratios: CircularBuffer::new(FAN_BASELINE_SAMPLES),
+            baselines: HashMap::new(),
+            warning: None
+        }
+    }
+
+    /// What is wrong with the fan, None while it looks healthy
+    pub fn warning(&self) -> Option<&String> {
+        self.warning.as_ref()
+    }
+
+    /**
+        Takes the duty (%) and RPM read back in an update and returns the
+        warning when a new problem shows up. Baselines are learned per duty
+        band from the first FAN_BASELINE_SAMPLES steady samples in it.
+    **/
+    pub fn sample(&mut self, now: Instant, speed: f64, rpm: u32, ramp_timeout: Duration, max_deviation: f64) -> Option<String> {
+        let last = self.last.replace((speed, rpm));
+        let warning = self.track_ramp(now, last, speed, rpm, ramp_timeout)
+            .or_else(|| self.track_baseline(speed, rpm, max_deviation));
+
+        // Only a new problem is returned, the text of an ongoing one is just kept current
+        let warning = warning?;
+        let new = self.warning.is_none();
+        self.warning = Some(warning.clone());
+        if new { Some(warning) } else { None }
+    }
+
+    fn track_ramp(&mut self, now: Instant, last: Option<(f64, u32)>, speed: f64, rpm: u32, timeout: Duration) -> Option<String> {
+        let ramp = match (&mut self.ramp, last) {
+            (Some(ramp), _) => ramp,
+            (None, Some((last_speed, last_rpm))) if speed > last_speed => {
+                self.ramp = Some(Ramp { from_speed: last_speed, from_rpm: last_rpm, reached: None });
+                return None;
+            },
+            _ => return None
+        };
+
+        if rpm >= ramp.from_rpm + FAN_RAMP_RPM_RISE || speed <= ramp.from_speed {
+            self.ramp = None;
+            self.warning = None;
+            return None;
+        }
+        if ramp.reached.is_none() && speed >= ramp.from_speed + FAN_RAMP_MIN_STEP {
+            ramp.reached = Some(now);
+        }
+
+        match ramp.reached {
+            Some(reached) if now.duration_since(reached) > timeout => {
+                let warning = format!("Fan stayed at {} RPM {:.0}s after going from {:.0}% to {:.0}%",
+                    rpm, timeout.as_secs_f32(), ramp.from_speed, speed);
+                self.ramp = None;
+                Some(warning)
+            },
+            _ => None
+        }
+    }
+
+    fn track_baseline(&mut self, speed: f64, rpm: u32, max_deviation: f64) -> Option<String> {
+        // Samples during a ramp or with a stopped fan say nothing about its steady RPM
+        if self.ramp.is_some() || speed <= 0f64 || rpm == 0 {
+            return None;
+        }
+
+        let band = (speed / FAN_BASELINE_BAND) as u32;
+        if self.band != Some(band) {
+            self.band = Some(band);
+            self.ratios = CircularBuffer::new(FAN_BASELINE_SAMPLES);
+        }
+        self.ratios.add(rpm as f64 / speed);
+        if self.ratios.len() < FAN_BASELINE_SAMPLES {
+            return None;
+        }
+
+        let ratio = self.ratios.iter().sum::<f64>() / self.ratios.len() as f64;
+        let baseline = *self.baselines.entry(band).or_insert(ratio);
+        let deviation = (ratio - baseline).abs() / baseline * 100f64;
+
+        if deviation > max_deviation {
+            Some(format!("Fan runs at {:.0}% of its learned RPM around {:.0}% duty", ratio / baseline * 100f64,
+                band as f64 * FAN_BASELINE_BAND))
+        } else {
+            self.warning = None;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn warns_when_rpm_lags_a_ramp() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut health = FanHealth::new();
+
+        assert_eq!(health.sample(at(0), 30f64, 1000, TIMEOUT, 30f64), None);
+        assert_eq!(health.sample(at(1), 50f64, 1000, TIMEOUT, 30f64), None);
+        assert_eq!(health.sample(at(3), 50f64, 1050, TIMEOUT, 30f64), None);
+        assert!(health.sample(at(9), 50f64, 1050, TIMEOUT, 30f64).is_some());
+        assert!(health.warning().is_some());
+
+        // A fan which answers in time is fine
+        let mut health = FanHealth::new();
+        health.sample(at(0), 30f64, 1000, TIMEOUT, 30f64);
+        health.sample(at(1), 50f64, 1000, TIMEOUT, 30f64);
+        health.sample(at(3), 50f64, 1400, TIMEOUT, 30f64);
+        assert_eq!(health.sample(at(9), 50f64, 1400, TIMEOUT, 30f64), None);
+        assert_eq!(health.warning(), None);
+    }
+
+    #[test]
+    fn warns_when_rpm_drifts_from_baseline() {
+        let now = Instant::now();
+        let mut health = FanHealth::new();
+
+        for _ in 0..FAN_BASELINE_SAMPLES {
+            assert_eq!(health.sample(now, 40f64, 1200, TIMEOUT, 30f64), None);
+        }
+        for _ in 0..FAN_BASELINE_SAMPLES {
+            health.sample(now, 40f64, 700, TIMEOUT, 30f64);
+        }
+
+        assert_eq!(health.warning().map(String::as_str), Some("Fan runs at 58% of its learned RPM around 40% duty"));
+    }
+}
@@ -9,12 +9,15 @@ use std::path::Path;
 pub trait GenericSysFsFan {
     fn sysfs_pwm_file(&self) -> &PathBuf;
     fn sysfs_pwm_enable_file(&self) -> &PathBuf;
+    fn sysfs_rpm_file(&self) -> &PathBuf;
+    fn sysfs_target_rpm_file(&self) -> &PathBuf;
 
     fn parse_mode(value: u8) -> FanMode;
     fn dump_mode(mode: FanMode) -> u8;
 }
 
-pub fn build_sysfs_paths<P: AsRef<Path>>(sysfs_dir: P, index: u32) -> Option<(PathBuf, PathBuf)> {
+/// Returns pwm, pwm enable, rpm and target rpm paths. The rpm files are optional, so their presence isn't checked.
+pub fn build_sysfs_paths<P: AsRef<Path>>(sysfs_dir: P, index: u32) -> Option<(PathBuf, PathBuf, PathBuf, PathBuf)> {
     let sysfs_dir_ref = sysfs_dir.as_ref();
     let base_file: PathBuf = sysfs_dir_ref.to_path_buf();
 
@@ -24,6 +27,8 @@ pub fn build_sysfs_paths<P: AsRef<Path>>(sysfs_dir: P, index: u32) -> Option<(Pa
 
     let pwm_file: PathBuf = sysfs_dir_ref.join(format!("pwm{}", index));
     let pwm_enable_file: PathBuf = sysfs_dir_ref.join(format!("pwm{}_enable", index));
+    let rpm_file: PathBuf = sysfs_dir_ref.join(format!("fan{}_input", index));
+    let target_rpm_file: PathBuf = sysfs_dir_ref.join(format!("fan{}_target", index));
 
     for path in [&pwm_file, &pwm_enable_file].iter() {
         if !path.is_file() {
@@ -31,20 +36,37 @@ pub fn build_sysfs_paths<P: AsRef<Path>>(sysfs_dir: P, index: u32) -> Option<(Pa
         }
     }
 
-    return Some((pwm_file, pwm_enable_file));
+    Some((pwm_file, pwm_enable_file, rpm_file, target_rpm_file))
 }
 
 impl<T: GenericSysFsFan> FanControl for T {
     fn speed(&self) -> ClampedPercentage {
-        let value: u8 = sysfs::parse_string_from_file(&self.sysfs_pwm_file());
+        self.try_speed().expect("Failed to read fan speed")
+    }
+
+    fn try_speed(&self) -> Option<ClampedPercentage> {
+        let value: u8 = sysfs::try_parse_string_from_file(&self.sysfs_pwm_file())?;
 
-        ClampedPercentage::new(value as f32 / 255f32 * 100f32)
+        Some(ClampedPercentage::new(value as f32 / 255f32 * 100f32))
     }
 
     fn set_speed(&self, speed: ClampedPercentage) {
         let value: u8 = (speed.0 * 255f64 / 100f64) as u8;
 
-        sysfs::write(&self.sysfs_pwm_file(), &value.to_string());
+        sysfs::write(self.sysfs_pwm_file(), &value.to_string());
+    }
+
+    fn rpm(&self) -> Option<u32> {
+        sysfs::try_parse_string_from_file(self.sysfs_rpm_file())
+    }
+
+    fn target_rpm(&self) -> Option<u32> {
+        sysfs::try_parse_string_from_file(self.sysfs_target_rpm_file())
+    }
+
+    fn is_firmware_controlled(&self) -> bool {
+        sysfs::try_parse_string_from_file::<u8, _>(self.sysfs_pwm_enable_file())
+            .is_some_and(|value| value == T::dump_mode(FanMode::Auto))
     }
 
     fn mode(&self) -> FanMode { 
@@ -56,6 +78,6 @@ impl<T: GenericSysFsFan> FanControl for T {
     fn set_mode(&self, mode: FanMode) {
         let value = T::dump_mode(mode);
 
-        sysfs::write(&self.sysfs_pwm_enable_file(), &value.to_string());
+        sysfs::write(self.sysfs_pwm_enable_file(), &value.to_string());
     }
 }
@@ -0,0 +1,29 @@
+use std::process::Command;
+use std::thread;
+
+/**
+    Runs a user supplied shell command without waiting for it to finish.
+    Arguments are available to the command as $1, $2... and env vars
+    are passed as is. Failures are only logged.
+**/
+pub fn run(command: &str, args: &[String], env: &[(&str, String)]) {
+    let spawned = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .arg("sentinel")
+        .args(args)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .spawn();
+
+    match spawned {
+        Ok(mut child) => {
+            let command = command.to_string();
+            thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => println!("! Hook `{}` failed with {}", command, status),
+                Ok(_) => {},
+                Err(err) => println!("! Hook `{}` failed: {}", command, err)
+            });
+        },
+        Err(err) => println!("! Failed to run hook `{}`: {}", command, err)
+    }
+}
@@ -1,10 +1,13 @@
-use std::{fs::File, convert::TryInto};
+use std::convert::TryInto;
 use std::ops::Div;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{thread, time};
-use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use std::panic;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::io::prelude::*;
+use std::fmt;
+use std::ops::RangeInclusive;
 
 extern crate signal_hook;
 extern crate num;
@@ -12,6 +15,7 @@ extern crate num;
 mod fan;
 use fan::*;
 mod sysfs;
+use sysfs::WritePriority;
 mod polaris_gpu;
 use polaris_gpu::*;
 mod clamped_percentage;
@@ -22,163 +26,756 @@ mod circular_buffer;
 use circular_buffer::CircularBuffer;
 mod polaris_gpu_fan;
 mod generic_sysfs_fan;
-mod nct6797_fan;
 mod polaris_gpu_table;
-use polaris_gpu_table::{PolarisGpuTable, PolarisGpuState};
+use polaris_gpu_table::{PolarisGpuTable, PolarisGpuState, StateInvalidReason};
 mod performance_level;
 use performance_level::{PerformanceLevel, ControllablePerformanceLevel};
 mod amdgpu_performance_level;
 mod sysfs_device;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+mod config;
+use config::{CardLimits, Config, ConfigError, EmergencySensorMode, FanCurves, PerformanceDecision, PreflightCheck, StartupState,
+    USAGE_WINDOW_UPDATES, TEMPERATURE_WINDOW_UPDATES, TEMPERATURE_SLOPE_UPDATES, POWER_WINDOW_UPDATES};
+mod args;
+use args::Args;
+mod curve;
+use curve::{Curve, CurveInterpolation, CurvePoint};
+mod hook;
+mod card_state;
+mod snapshot;
+use snapshot::GpuSnapshot;
+mod monitor;
+#[cfg(feature = "sqlite")]
+mod telemetry_db;
+mod events;
+use events::{Event, EventPipe};
+mod fan_health;
+use fan_health::FanHealth;
+mod slew_limiter;
+use slew_limiter::SlewLimiter;
+mod rpm_controller;
+use rpm_controller::RpmController;
+mod schedule;
+mod process;
+mod preflight;
+use preflight::PreflightError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
 pub enum GpuCustomState {
     Idle,
     CoolOff,
     Performance,
-    Mining
+    Mining,
+    Emergency
+}
+
+/// How busy the short usage window is compared to the long one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageLoad {
+    /// The short window is below active_usage_threshold
+    Light,
+    /// Only the short window is busy, a spike which may be over soon
+    Transient,
+    /// The long window is above sustained_usage_threshold too
+    Sustained
 }
 
 pub struct GpuStateMachine {
     state: GpuCustomState,
     usage_buffer: CircularBuffer::<f64>,
     temperature_buffer: CircularBuffer::<f32>,
+    temperature_samples: CircularBuffer::<(Instant, f32)>,
+    rising_fast: bool,
+    performance_exit_pending: Option<Instant>,
+    /// Nothing was applied yet with an Auto startup, so the first decision is applied even if unchanged
+    startup_pending: bool,
+    emergency_entered: Option<Instant>,
+    emergency_cooled_since: Option<Instant>,
     power_usage_buffer: CircularBuffer::<f32>,
+    instant_power: Option<f32>,
+    /// Whether the last power reading was usable, see PolarisGpu::measured_power_usage
+    power_measured: bool,
+    /// performance_gate_process isn't running, so Performance is off limits
+    performance_gated: bool,
+    usage_load: UsageLoad,
+    fan_rpm: Option<u32>,
+    /// RPM the firmware, or with fan_rpm_full_speed the RPM controller, aims for
+    fan_target_rpm: Option<u32>,
+    /// Estimated fan loudness (dBA), see PolarisGpu::estimated_noise
+    fan_noise: Option<f32>,
+    stall_rpm_buffer: CircularBuffer::<u32>,
+    fan_health: FanHealth,
+    fan_speed: Option<ClampedPercentage>,
+    fan_firmware_controlled: bool,
+    clocks: (Option<u32>, Option<u32>),
+    /// Measured and table core voltage (mV)
+    core_voltages: (Option<u32>, Option<u32>),
+    /// Correctable and uncorrectable memory errors counted when the daemon started
+    memory_errors_at_start: Option<(u64, u64)>,
+    /// Memory errors since then, None without RAS support
+    memory_errors: Option<(u64, u64)>,
+    temperatures: TemperatureReadings,
+    power_profiles: Vec<(GpuCustomState, u8)>,
+    /// Whether the card lists more than one PCIe level, see resolve_pcie_switching
+    pcie_switchable: bool,
     idle_table: PolarisGpuTable,
     performance_table: PolarisGpuTable,
     mining_table: PolarisGpuTable,
-    performance_curve: Curve
-}
-
-#[derive(Clone)]
-pub struct CurvePoint {
-    temperature: u32,
-    fan_speed: ClampedPercentage
+    performance_curve: Curve,
+    idle_curve: Option<Curve>,
+    cooloff_curve: Option<Curve>,
+    mining_curve: Option<Curve>,
+    active_curve: Option<Curve>,
+    events: Option<EventPipe>,
+    fan_target: Option<ClampedPercentage>,
+    fan_slew: SlewLimiter,
+    /// Set with fan_rpm_full_speed, turns fan speeds into RPM targets
+    rpm_controller: Option<RpmController>,
+    power_limit_target: Option<f32>,
+    power_limit_slew: SlewLimiter,
+    scheduled_level: Option<PerformanceLevel>,
+    /// Power limit lowered by the thermal governor, None while it isn't holding anything back
+    governed_power_limit: Option<f32>,
+    read_only: bool,
+    config: Config
 }
 
-pub struct Curve {
-    points: Vec<CurvePoint>
+/// Samples a buffer holds once full and the time they span
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferWindow {
+    pub samples: usize,
+    pub seconds: f32
 }
 
-pub enum CurveInterpolation {
-    Linear
+/// Time spans of the averaging buffers, which follow from samples_per_update and update_interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowSummary {
+    pub usage: BufferWindow,
+    pub temperature: BufferWindow,
+    pub temperature_slope: BufferWindow,
+    pub power: BufferWindow,
+    pub fan_stall: BufferWindow
 }
 
-impl Curve {
-    pub fn new(points: Vec::<CurvePoint>) -> Self {
-        if points.len() < 1 {
-            panic!("Invalid curve without any point");
+impl fmt::Display for WindowSummary {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let windows = [
+            ("usage", self.usage),
+            ("temperature", self.temperature),
+            ("temperature slope", self.temperature_slope),
+            ("power", self.power),
+            ("fan stall", self.fan_stall)
+        ];
+        for (i, (name, window)) in windows.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{} window: {:.1}s over {} samples", name, window.seconds, window.samples)?;
         }
-        let mut points_vec = points.to_vec();
-        points_vec.sort_by_key(|pt| pt.temperature);
-
-        Curve { points: points_vec }
+        Ok(())
     }
 
-    fn interpolate(value: f32, lower: &CurvePoint, upper: &CurvePoint, interpolation: CurveInterpolation) -> ClampedPercentage {
-        let temp_delta: f32 = (upper.temperature - lower.temperature) as f32;
-        let speed_delta: f32 = (upper.fan_speed.0 - lower.fan_speed.0) as f32;
-        let diff: f32 = value - lower.temperature as f32;
-
-        let value: f32 = match interpolation {
-            CurveInterpolation::Linear => {
-                (lower.fan_speed.0 as f32) + (diff / temp_delta) * speed_delta
-            }
-        };
+}
 
-        ClampedPercentage::new(value)
+fn weighted_average<T: num::Float>(buffer: &CircularBuffer<T>) -> Option<T> {
+    if buffer.is_empty() {
+        None
+    } else {
+        Some(index_weighted_average(buffer.iter()))
     }
+}
 
-    pub fn get(&self, temperature: f32, interpolation: CurveInterpolation) -> ClampedPercentage {
-        for (idx, point) in self.points.iter().enumerate().rev() {
-            if temperature as u32 >= point.temperature {
-                // Get next point or the same if last
-                let next_point = self.points.get(idx + 1).unwrap_or(point);
-                return Self::interpolate(temperature, point, next_point, interpolation);
-            }
-        }
-
-        self.points.get(0).expect("Curve must have at least one point").fan_speed
+fn latest<T: Copy>(buffer: &CircularBuffer<T>) -> Option<T> {
+    if buffer.is_empty() {
+        None
+    } else {
+        buffer.iter().next_back().copied()
     }
 }
 
+/// A pwm step is ~0.4%, so a read back speed within this is the one written
+const FAN_SPEED_TOLERANCE: f64 = 1.0;
+
+/// Readings below this while the fan is commanded to spin mean it is not spinning
+const FAN_STALL_RPM: u32 = 100;
+
+/// Lets the firmware spin the fan up before clocks and power are handed back
+const SHUTDOWN_FAN_SETTLE_TIME: time::Duration = time::Duration::from_millis(500);
+
+/// How long the driver gets to repopulate the table after a reset
+const PSTATE_RESET_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
 static MINER_NAMES: &[&str] = &[
     "lolMiner",
     "teamredminer",
     "xmrig"
 ];
 
-#[cfg(target_os = "linux")]
-fn procfs_get_name(dir: std::fs::DirEntry) -> Option<String> {
-    dir.file_name().to_str()
-        // If directory name is a pid
-        .filter(|dirname| dirname.chars().all(char::is_numeric))
-        .map(|_| dir.path().join("comm"))
-        // Open /proc/<PID>/comm file
-        .and_then(|path| File::open(path).ok())
-        .as_mut()
-        // Read the name from comm file
-        .and_then(|file| {
-            let mut name = String::new();
-            match file.read_to_string(&mut name) {
-                Ok(_) => Some(name),
-                Err(_) => None
-            }
-        })
-}
-
-
-#[cfg(target_os = "linux")]
-fn is_mining() -> bool {
-    std::fs::read_dir("/proc/")
-        .expect("No /proc?!")
-        .flat_map(|dir_or_err| dir_or_err)
-        .flat_map(procfs_get_name)
-        // Is any process a miner?
-        .any(|name| MINER_NAMES.contains(&name.as_str().trim()))
-}
-
 impl GpuStateMachine {
 
     pub fn state(&self) -> GpuCustomState {
         self.state
     }
 
+    /// Every buffer gets a sample per gather, samples_per_update of them per update_interval
+    pub fn window_summary(&self) -> WindowSummary {
+        let sample_interval = self.config.update_interval / self.config.samples_per_update as f32;
+        let window = |samples: usize| BufferWindow { samples, seconds: samples as f32 * sample_interval };
+
+        WindowSummary {
+            usage: window(self.usage_buffer.capacity()),
+            temperature: window(self.temperature_buffer.capacity()),
+            temperature_slope: window(self.temperature_samples.capacity()),
+            power: window(self.power_usage_buffer.capacity()),
+            fan_stall: window(self.stall_rpm_buffer.capacity())
+        }
+    }
+
+    pub fn fan_rpm(&self) -> Option<u32> {
+        self.fan_rpm
+    }
+
     pub fn new(buffer_scale: usize,
         idle_table: PolarisGpuTable,
         performance_table: PolarisGpuTable,
         mining_table: PolarisGpuTable,
-        curve: Curve) -> Self {
+        config: Config,
+        read_only: bool) -> Self {
         GpuStateMachine {
             state: GpuCustomState::Idle,
-            usage_buffer: CircularBuffer::new(20 * buffer_scale),
-            temperature_buffer: CircularBuffer::new(10 * buffer_scale),
-            power_usage_buffer: CircularBuffer::new(5 * buffer_scale),
-            performance_curve: curve,
+            usage_buffer: CircularBuffer::new(USAGE_WINDOW_UPDATES * buffer_scale),
+            temperature_buffer: CircularBuffer::new(TEMPERATURE_WINDOW_UPDATES * buffer_scale),
+            temperature_samples: CircularBuffer::new(TEMPERATURE_SLOPE_UPDATES * buffer_scale + 1),
+            rising_fast: false,
+            performance_exit_pending: None,
+            startup_pending: false,
+            emergency_entered: None,
+            emergency_cooled_since: None,
+            power_usage_buffer: CircularBuffer::new(POWER_WINDOW_UPDATES * buffer_scale),
+            instant_power: None,
+            power_measured: false,
+            performance_gated: false,
+            usage_load: UsageLoad::Light,
+            fan_rpm: None,
+            fan_target_rpm: None,
+            fan_noise: None,
+            stall_rpm_buffer: CircularBuffer::new(config.fan_failure_samples),
+            fan_health: FanHealth::new(),
+            fan_speed: None,
+            fan_firmware_controlled: false,
+            clocks: (None, None),
+            core_voltages: (None, None),
+            memory_errors_at_start: None,
+            memory_errors: None,
+            temperatures: TemperatureReadings::default(),
+            power_profiles: vec![],
+            pcie_switchable: false,
+            performance_curve: Curve::new(config.fan_curve.clone()),
+            idle_curve: config.idle_fan_curve.clone().map(Curve::new),
+            cooloff_curve: Some(cooloff_curve(&config)),
+            mining_curve: config.mining_fan_curve.clone().map(Curve::new),
+            active_curve: None,
+            events: config.event_pipe.as_ref().map(EventPipe::new),
+            fan_target: None,
+            fan_slew: SlewLimiter::symmetric(config.max_fan_slew_per_sec),
+            rpm_controller: config.fan_rpm_full_speed.map(|full_speed| RpmController::new(full_speed, config.fan_rpm_gain)),
+            power_limit_target: None,
+            power_limit_slew: SlewLimiter::new(config.power_limit_rise_rate, config.power_limit_fall_rate),
+            scheduled_level: None,
+            governed_power_limit: None,
             idle_table,
             performance_table,
-            mining_table
+            mining_table,
+            read_only,
+            config
         }
     }
 
+    /**
+        Samples every signal on its own, so a failing read only skips that
+        signal while its buffer keeps the previous history.
+    **/
     pub fn update(&mut self, gpu: &PolarisGpu<'_>) {
-        self.usage_buffer.add(gpu.usage().0);
-        self.temperature_buffer.add(gpu.temperature());
-        self.power_usage_buffer.add(gpu.power_usage());
+        match gpu.try_usage() {
+            Some(usage) => self.usage_buffer.add(usage.0),
+            None => println!("! Failed to read gpu usage")
+        }
+        self.temperatures = gpu.all_temperatures();
+        match self.temperatures.control_temperature(self.config.junction_weight) {
+            Some(temperature) => {
+                self.temperature_buffer.add(temperature);
+                self.temperature_samples.add((Instant::now(), temperature));
+            },
+            None => println!("! Failed to read gpu temperature")
+        }
+        // A 0W reading above the lowest state is unsupported rather than idle, so it's left out of the average
+        let power_usage = gpu.measured_power_usage();
+        self.power_measured = power_usage.is_some();
+        match power_usage {
+            Some(power_usage) => self.power_usage_buffer.add(power_usage),
+            None if gpu.try_power_usage().is_some() => println!("! Ignoring 0W power reading above the lowest core state"),
+            None => println!("! Failed to read gpu power usage")
+        }
+        if self.config.instant_power_entry {
+            self.instant_power = gpu.power_instant();
+        }
+
+        // Without a controllable pwm the firmware drives the fan
+        self.fan_speed = gpu.fan().and_then(|fan| fan.try_speed());
+        self.fan_firmware_controlled = gpu.fan().is_none_or(|fan| fan.is_firmware_controlled());
+        self.fan_target_rpm = match (&self.rpm_controller, self.fan_target) {
+            (Some(controller), Some(target)) if !self.fan_firmware_controlled => Some(controller.target_rpm(target)),
+            _ => gpu.fan().and_then(|fan| fan.target_rpm())
+        };
+        self.fan_noise = gpu.estimated_noise();
+        self.clocks = (gpu.current_clock(Part::Core), gpu.current_clock(Part::Memory));
+        self.track_core_voltage(gpu);
+        self.track_memory_errors(gpu);
+
+        // The tachometer glitches during transitions, keep the previous reading then
+        match gpu.fan().and_then(|fan| fan.rpm()) {
+            Some(rpm) if rpm > self.config.fan_rpm_plausible_max =>
+                println!("Ignoring implausible fan speed {} RPM", rpm),
+            rpm => {
+                self.fan_rpm = rpm;
+                if let Some(rpm) = rpm {
+                    self.track_fan_stall(rpm);
+                    self.track_fan_health(rpm);
+                }
+            }
+        }
+    }
+
+    /// The firmware ramps the fan its own way, so only speeds we command are judged
+    fn track_fan_health(&mut self, rpm: u32) {
+        let speed = match self.fan_speed {
+            Some(speed) if !self.fan_firmware_controlled => speed.0,
+            _ => return
+        };
+
+        let timeout = time::Duration::from_secs_f32(self.config.fan_ramp_timeout);
+        if let Some(warning) = self.fan_health.sample(Instant::now(), speed, rpm, timeout, self.config.fan_baseline_deviation) {
+            self.warn(warning);
+        }
+    }
+
+    /// Only samples taken while the fan is commanded to spin count towards a failure
+    fn track_fan_stall(&mut self, rpm: u32) {
+        let commanded = self.fan_speed.is_some_and(|speed| speed.0 > self.config.fan_failure_min_speed);
+
+        if commanded {
+            self.stall_rpm_buffer.add(rpm);
+        } else if !self.stall_rpm_buffer.is_empty() {
+            self.stall_rpm_buffer = CircularBuffer::new(self.config.fan_failure_samples);
+        }
+    }
+
+    /// A single 0 RPM reading is often a tachometer dropout, so every recent sample has to agree
+    fn fan_stalled(&self) -> bool {
+        self.stall_rpm_buffer.len() == self.config.fan_failure_samples &&
+            self.stall_rpm_buffer.iter().all(|rpm| *rpm < FAN_STALL_RPM)
+    }
+
+    pub fn snapshot(&self) -> GpuSnapshot {
+        GpuSnapshot {
+            state: self.state,
+            temperature: latest(&self.temperature_buffer),
+            weighted_temperature: weighted_average(&self.temperature_buffer),
+            junction_temperature: self.temperatures.junction,
+            memory_temperature: self.temperatures.memory,
+            usage: latest(&self.usage_buffer),
+            weighted_usage: weighted_average(&self.usage_buffer),
+            power_usage: latest(&self.power_usage_buffer).filter(|_| self.power_measured),
+            fan_speed: self.fan_speed,
+            fan_rpm: self.fan_rpm,
+            fan_target_rpm: self.fan_target_rpm,
+            fan_noise: self.fan_noise,
+            fan_firmware_controlled: self.fan_firmware_controlled,
+            fan_health: self.fan_health.warning().cloned(),
+            transition_block: self.transition_block_reason(),
+            core_clock: self.clocks.0,
+            memory_clock: self.clocks.1,
+            core_voltage: self.core_voltages.0,
+            table_voltage: self.core_voltages.1,
+            voltage_diverged: self.voltage_diverged(),
+            memory_errors: self.memory_errors
+        }
+    }
+
+    /// Measured core voltage off the table's set point by more than voltage_discrepancy_margin
+    fn voltage_diverged(&self) -> bool {
+        match self.core_voltages {
+            (Some(measured), Some(set)) => measured.abs_diff(set) > self.config.voltage_discrepancy_margin,
+            _ => false
+        }
+    }
+
+    fn track_core_voltage(&mut self, gpu: &PolarisGpu<'_>) {
+        let was_diverged = self.voltage_diverged();
+        self.core_voltages = (gpu.core_voltage(), gpu.current_table_voltage());
+
+        // Load-line droop makes the measured voltage sag under load, that's not a failed undervolt
+        if let ((Some(measured), Some(set)), true) = (self.core_voltages, self.voltage_diverged() && !was_diverged) {
+            self.warn(format!("Core measures {}mV against {}mV in the table", measured, set));
+        }
+    }
+
+    /// Counts the memory errors of this session, a rising count means the memory clock is pushed too far
+    fn track_memory_errors(&mut self, gpu: &PolarisGpu<'_>) {
+        let counts = match gpu.memory_errors() {
+            Some(counts) => counts,
+            None => return
+        };
+
+        let start = *self.memory_errors_at_start.get_or_insert(counts);
+        let session = (counts.0.saturating_sub(start.0), counts.1.saturating_sub(start.1));
+        let previous = self.memory_errors.replace(session).unwrap_or((0, 0));
+        if session.0 + session.1 > previous.0 + previous.1 {
+            self.warn(format!("{} correctable and {} uncorrectable memory errors since startup, consider lowering the memory clock",
+                session.0, session.1));
+        }
+    }
+
+    /// Temperature change in C/s between the oldest and newest recent sample
+    pub fn temperature_slope(&self) -> Option<f32> {
+        if self.temperature_samples.len() < 2 {
+            return None;
+        }
+
+        let mut samples = self.temperature_samples.iter();
+        let (oldest_time, oldest) = samples.next()?;
+        let (newest_time, newest) = samples.next_back()?;
+        let elapsed = newest_time.duration_since(*oldest_time).as_secs_f32();
+
+        if elapsed > 0f32 {
+            Some((newest - oldest) / elapsed)
+        } else {
+            None
+        }
+    }
+
+    /// Looks up the configured power profile names on the card, unknown ones are skipped
+    pub fn resolve_power_profiles(&mut self, gpu: &PolarisGpu<'_>) {
+        let configured = [
+            (GpuCustomState::Idle, &self.config.idle_power_profile),
+            (GpuCustomState::Performance, &self.config.performance_power_profile),
+            (GpuCustomState::Mining, &self.config.mining_power_profile)
+        ];
+
+        let mut resolved = vec![];
+        for (state, name) in configured.iter() {
+            if let Some(name) = name {
+                match gpu.power_profile_index(name) {
+                    Some(index) => resolved.push((*state, index)),
+                    None => println!("! Power profile {} is not available, {:?} keeps the current one", name, state)
+                }
+            }
+        }
+        self.power_profiles = resolved;
+    }
+
+    /**
+        Checks the config's preflight_checks before anything is written to the
+        card, collecting every failure so they can all be fixed in one go.
+    **/
+    pub fn preflight(gpu: &PolarisGpu<'_>, config: &Config, read_only: bool) -> Result<(), Vec<PreflightError>> {
+        let mut errors = Vec::new();
+
+        for check in config.preflight_checks.iter() {
+            match check {
+                PreflightCheck::Nodes => errors.extend(gpu.validate_nodes(read_only).missing_required.into_iter()
+                    .map(|(path, feature)| PreflightError::MissingNode(path, feature))),
+                PreflightCheck::Writable if !read_only => errors.extend(gpu.control_files().into_iter()
+                    .filter(|path| !sysfs::is_writable(path))
+                    .map(PreflightError::NotWritable)),
+                PreflightCheck::Writable => {},
+                PreflightCheck::EmergencySensor => {
+                    let readings = gpu.all_temperatures();
+                    let readable = match config.emergency_sensor_mode {
+                        EmergencySensorMode::Edge => readings.edge.is_some(),
+                        EmergencySensorMode::Hottest | EmergencySensorMode::Average =>
+                            readings.edge.or(readings.junction).or(readings.memory).is_some()
+                    };
+                    if !readable {
+                        errors.push(PreflightError::NoEmergencySensor(config.emergency_sensor_mode));
+                    }
+                },
+                PreflightCheck::FanControl => if gpu.fan().is_none() {
+                    errors.push(PreflightError::NoFanControl);
+                },
+                PreflightCheck::PowerRange => match gpu.try_power_limit_range() {
+                    Some(range) => {
+                        let limits = [
+                            ("idle_power_limit", config.idle_power_limit),
+                            ("performance_power_limit", config.performance_power_limit),
+                            ("mining_power_limit", config.mining_power_limit)
+                        ];
+                        errors.extend(limits.iter()
+                            .filter(|(_, limit)| !range.contains(limit))
+                            .map(|(name, limit)| PreflightError::PowerLimitOutOfRange { name, limit: *limit, range: range.clone() }));
+                    },
+                    None => errors.push(PreflightError::PowerRangeUnreadable)
+                }
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Warns about configured PCIe levels the card can't switch to, they are skipped then
+    pub fn resolve_pcie_switching(&mut self, gpu: &PolarisGpu<'_>) {
+        self.pcie_switchable = gpu.supports_pcie_switching();
+        let configured = self.config.idle_pcie_level.is_some() || self.config.performance_pcie_level.is_some();
+        if configured && !self.pcie_switchable {
+            println!("! The card can't switch PCIe levels, idle_pcie_level and performance_pcie_level are ignored");
+        }
+    }
+
+    fn set_pcie_level(&self, gpu: &PolarisGpu<'_>, state: GpuCustomState) {
+        let level = match state {
+            GpuCustomState::Idle => self.config.idle_pcie_level,
+            GpuCustomState::Performance => self.config.performance_pcie_level,
+            _ => None
+        };
+
+        if let Some(level) = level.filter(|_| self.pcie_switchable) {
+            if let Err(err) = gpu.try_set_pcie_level(level) {
+                self.warn(format!("Failed to set the PCIe level to {:?}: {}", level, err));
+            }
+        }
+    }
+
+    /// The driver only takes a profile under the manual performance level, other levels keep the current one
+    fn set_power_profile(&self, gpu: &PolarisGpu<'_>, state: GpuCustomState) {
+        if self.performance_level(state) != PerformanceLevel::Manual {
+            return;
+        }
+        if let Some((_, index)) = self.power_profiles.iter().find(|(profile_state, _)| *profile_state == state) {
+            if let Err(err) = gpu.try_set_power_profile_mode(*index) {
+                self.warn(format!("Failed to set the power profile of {:?}: {}", state, err));
+            }
+        }
+    }
+
+    /**
+        Swaps in another config along with the tables derived from it and
+        reapplies the current state. Sample history is kept unless the
+        buffer sizes change.
+    **/
+    pub fn switch_config(&mut self, gpu: &PolarisGpu<'_>,
+        config: Config,
+        idle_table: PolarisGpuTable,
+        performance_table: PolarisGpuTable,
+        mining_table: PolarisGpuTable) {
+        if config.samples_per_update != self.config.samples_per_update {
+            let buffer_scale = config.samples_per_update;
+            self.usage_buffer = CircularBuffer::new(USAGE_WINDOW_UPDATES * buffer_scale);
+            self.temperature_buffer = CircularBuffer::new(TEMPERATURE_WINDOW_UPDATES * buffer_scale);
+            self.temperature_samples = CircularBuffer::new(TEMPERATURE_SLOPE_UPDATES * buffer_scale + 1);
+            self.power_usage_buffer = CircularBuffer::new(POWER_WINDOW_UPDATES * buffer_scale);
+        }
+        if config.fan_failure_samples != self.config.fan_failure_samples {
+            self.stall_rpm_buffer = CircularBuffer::new(config.fan_failure_samples);
+        }
+
+        self.set_curves(&config);
+        self.events = config.event_pipe.as_ref().map(EventPipe::new);
+
+        // Keep slewing from the speed the fan is at
+        let mut fan_slew = SlewLimiter::symmetric(config.max_fan_slew_per_sec);
+        if let Some(speed) = self.fan_slew.last() {
+            fan_slew.seed(speed, Instant::now());
+        }
+        self.fan_slew = fan_slew;
+        self.rpm_controller = config.fan_rpm_full_speed.map(|full_speed| RpmController::new(full_speed, config.fan_rpm_gain));
+
+        let mut power_limit_slew = SlewLimiter::new(config.power_limit_rise_rate, config.power_limit_fall_rate);
+        if let Some(limit) = self.power_limit_slew.last() {
+            power_limit_slew.seed(limit, Instant::now());
+        }
+        self.power_limit_slew = power_limit_slew;
+
+        self.idle_table = idle_table;
+        self.performance_table = performance_table;
+        self.mining_table = mining_table;
+        self.config = config;
+
+        self.resolve_power_profiles(gpu);
+        self.resolve_pcie_switching(gpu);
+        self.apply(gpu, self.state);
+    }
+
+    /**
+        Swaps in new fan curves, keeping the state and sample history. The
+        curve of the current state takes over on the next step.
+    **/
+    pub fn set_fan_curves(&mut self, curves: &FanCurves) -> Result<(), ConfigError> {
+        let config = self.config.with_fan_curves(curves)?;
+        self.set_curves(&config);
+        self.config = config;
+        self.active_curve = self.fan_curve(self.state).cloned();
+        Ok(())
+    }
+
+    fn set_curves(&mut self, config: &Config) {
+        self.performance_curve = Curve::new(config.fan_curve.clone());
+        self.idle_curve = config.idle_fan_curve.clone().map(Curve::new);
+        self.cooloff_curve = Some(cooloff_curve(config));
+        self.mining_curve = config.mining_fan_curve.clone().map(Curve::new);
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(events) = &self.events {
+            events.emit(&event);
+        }
+    }
+
+    fn warn(&self, message: String) {
+        println!("! {}", message);
+        self.emit(Event::Warning { message });
+    }
+
+    /**
+        Blends usage (0-100%) and power usage normalized to the card's power
+        limit range into a single score, where 1.0 means both are maxed out.
+    **/
+    fn load_score(&self, usage: f64, power_usage: f32, power_range: &RangeInclusive<f32>) -> f64 {
+        let power_span = (power_range.end() - power_range.start()) as f64;
+        let normalized_usage = (usage / 100f64).clamp(0f64, 1f64);
+        let normalized_power = if power_span > 0f64 {
+            ((power_usage - power_range.start()) as f64 / power_span).clamp(0f64, 1f64)
+        } else {
+            0f64
+        };
+
+        self.config.usage_weight * normalized_usage + self.config.power_weight * normalized_power
+    }
+
+    /// The load has to stay low for performance_exit_delay before Performance or Mining is left
+    fn performance_exit_ready(&mut self, load_low: bool) -> bool {
+        let in_performance = matches!(self.state, GpuCustomState::Performance | GpuCustomState::Mining);
+        if !load_low || !in_performance {
+            self.performance_exit_pending = None;
+            return false;
+        }
+
+        let pending_since = *self.performance_exit_pending.get_or_insert_with(Instant::now);
+        pending_since.elapsed().as_secs_f32() >= self.config.performance_exit_delay
+    }
+
+    /// Why the current state is being held despite its exit condition, with the time left
+    pub fn transition_block_reason(&self) -> Option<String> {
+        let remaining = |since: Instant, duration: f32| (duration - since.elapsed().as_secs_f32()).max(0f32);
+
+        match self.state {
+            GpuCustomState::Emergency => Some(match self.emergency_cooled_since {
+                Some(since) => format!("emergency recovery hold: {:.0}s remaining",
+                    remaining(since, self.config.emergency_recovery_hold)),
+                None => format!("emergency: waiting for {}C or less",
+                    self.config.emergency_temperature - self.config.emergency_recovery_margin)
+            }),
+            GpuCustomState::Performance | GpuCustomState::Mining => self.performance_exit_pending
+                .map(|since| format!("performance exit delay: {:.0}s remaining",
+                    remaining(since, self.config.performance_exit_delay))),
+            GpuCustomState::Idle if self.config.filter_transient_load && self.usage_load == UsageLoad::Transient =>
+                Some("transient load: waiting for it to be sustained".to_string()),
+            GpuCustomState::Idle | GpuCustomState::CoolOff => None
+        }
+    }
+
+    /// Temperature the Emergency cutoff is checked against, falls back to the control temperature without sensors
+    fn emergency_temperature(&self, control_temperature: f32) -> f32 {
+        let readings = &self.temperatures;
+        let sensors: Vec<f32> = match self.config.emergency_sensor_mode {
+            EmergencySensorMode::Edge => readings.edge.into_iter().collect(),
+            EmergencySensorMode::Hottest | EmergencySensorMode::Average =>
+                [readings.edge, readings.junction, readings.memory].iter().flatten().copied().collect()
+        };
+
+        if sensors.is_empty() {
+            return control_temperature;
+        }
+        match self.config.emergency_sensor_mode {
+            EmergencySensorMode::Average => sensors.iter().sum::<f32>() / sensors.len() as f32,
+            _ => sensors.iter().copied().fold(f32::MIN, f32::max)
+        }
+    }
+
+    /**
+        Emergency is only left once the temperature stayed emergency_recovery_margin
+        below the cutoff for emergency_recovery_hold, so it doesn't thrash at the boundary.
+    **/
+    fn emergency_recovered(&mut self, temperature: f32) -> bool {
+        let cooled = temperature <= self.config.emergency_temperature - self.config.emergency_recovery_margin;
+        if self.state != GpuCustomState::Emergency || !cooled {
+            self.emergency_cooled_since = None;
+            return false;
+        }
+
+        let cooled_since = *self.emergency_cooled_since.get_or_insert_with(Instant::now);
+        cooled_since.elapsed().as_secs_f32() >= self.config.emergency_recovery_hold
+    }
+
+    fn wants_performance(&self, gpu: &PolarisGpu<'_>, usage: f64, power_usage: f32) -> bool {
+        match self.config.performance_decision {
+            PerformanceDecision::Thresholds =>
+                usage > self.config.saturated_usage_threshold ||
+                    (usage > self.config.active_usage_threshold && power_usage > self.config.performance_power_enter),
+            PerformanceDecision::LoadScore => {
+                let score = self.load_score(usage, power_usage, &gpu.power_limit_range());
+                score > self.config.performance_score_threshold
+            }
+        }
     }
 
     pub fn step(&mut self, gpu: &PolarisGpu<'_>){
+        if self.temperature_buffer.is_empty() {
+            self.warn("No temperature sampled yet, skipping step".to_string());
+            return;
+        }
+
         let current_temperature = *self.temperature_buffer.last();
-        let weighted_avg_usage = index_weighted_average(self.usage_buffer.iter());
+        // Signals which were never sampled successfully count as idle
+        let weighted_avg_usage = weighted_average(&self.usage_buffer).unwrap_or(0f64);
         let weighted_avg_temperature = index_weighted_average(self.temperature_buffer.iter());
-        let weighted_avg_power_usage = index_weighted_average(self.power_usage_buffer.iter());
-        let performance_treshold = 90f64;
-        let power_treshold = 50f32;
+        let weighted_avg_power_usage = weighted_average(&self.power_usage_buffer).unwrap_or(0f32);
 
         println!(" * {}C, weighted usage: {:.2}%, weighted temperature: {:.2}C",
             current_temperature, weighted_avg_usage, weighted_avg_temperature);
 
-        let new_state = if weighted_avg_usage > 95f64 || (weighted_avg_usage > 0.5f64 && weighted_avg_power_usage > 40f32) {
-            if is_mining() {
+        // Instantaneous power enters Performance faster, average power is still used to leave it
+        let entry_power_usage = self.instant_power.unwrap_or(weighted_avg_power_usage);
+
+        let fan_stalled = self.fan_stalled();
+        let schedule_changed = self.update_schedule();
+        let gated = self.update_performance_gate();
+        // With filter_transient_load a spike can't enter Performance, sustained load enters on the faster short window
+        let (usage_load, short_avg_usage) = self.update_usage_load();
+        let filtered = self.config.filter_transient_load;
+        let transient_held = filtered && usage_load == UsageLoad::Transient;
+        let entry_usage = if filtered && usage_load == UsageLoad::Sustained {
+            short_avg_usage.max(weighted_avg_usage)
+        } else {
+            weighted_avg_usage
+        };
+        // Power has to drop below the exit threshold, under the entry one, so a load hovering around it doesn't flap
+        let load_low = gated ||
+            (weighted_avg_power_usage < self.config.performance_power_exit && weighted_avg_usage < self.config.active_usage_threshold);
+        let performance_exit_ready = self.performance_exit_ready(load_low);
+        let emergency_temperature = self.emergency_temperature(current_temperature);
+        let emergency_recovered = self.emergency_recovered(emergency_temperature);
+
+        let new_state = if emergency_temperature >= self.config.emergency_temperature || fan_stalled {
+            GpuCustomState::Emergency
+        } else if self.state == GpuCustomState::Emergency {
+            if emergency_recovered {
+                // Still warm, so let CoolOff bring the temperature down
+                GpuCustomState::CoolOff
+            } else {
+                GpuCustomState::Emergency
+            }
+        } else if !gated && !transient_held && self.wants_performance(gpu, entry_usage, entry_power_usage) {
+            if process::any_running(MINER_NAMES) {
                 GpuCustomState::Mining
             } else {
                 GpuCustomState::Performance
@@ -186,111 +783,595 @@ impl GpuStateMachine {
         } else {
             match self.state {
                 GpuCustomState::Idle => {
-                    if weighted_avg_usage > performance_treshold {
+                    if !gated && !transient_held && entry_usage > self.config.performance_usage_enter {
                         GpuCustomState::Performance
-                    } else if current_temperature >= 55f32 {
+                    } else if current_temperature >= self.config.cooloff_enter_temperature {
                         GpuCustomState::CoolOff
                     } else {
                         self.state
                     }
                 },
                 GpuCustomState::CoolOff => {
-                    if weighted_avg_temperature <= 43f32 {
+                    if weighted_avg_temperature <= self.config.cooloff_exit_temperature {
                         GpuCustomState::Idle
                     } else {
                         self.state
                     }
                 },
                 GpuCustomState::Performance | GpuCustomState::Mining => {
-                    if weighted_avg_power_usage > power_treshold || weighted_avg_usage >= 10f64 {
-                        self.state
-                    } else {
+                    if performance_exit_ready {
                         GpuCustomState::Idle
+                    } else {
+                        self.state
                     }
-                }
+                },
+                GpuCustomState::Emergency => unreachable!("Emergency is left before usage is considered")
             }
         };
 
-        if new_state != self.state {
+        if new_state != self.state || self.startup_pending {
+            self.startup_pending = false;
+            self.emit(Event::Transition { from: self.state, to: new_state });
             self.apply(gpu, new_state);
+
+            if self.state == GpuCustomState::Emergency {
+                if let Some(entered) = self.emergency_entered.take() {
+                    println!("> Recovered from emergency after {:.0}s", entered.elapsed().as_secs_f32());
+                }
+            }
+
+            if new_state == GpuCustomState::Emergency {
+                self.emergency_entered = Some(Instant::now());
+                if fan_stalled {
+                    self.warn(format!("Fan is not spinning at {} RPM, assuming it failed", self.fan_rpm.unwrap_or(0)));
+                }
+                self.on_emergency(emergency_temperature);
+            }
+        } else if schedule_changed && new_state != GpuCustomState::Emergency && !self.read_only {
+            let level = self.performance_level(new_state);
+            println!("> Schedule switched the performance level to {}", level);
+            gpu.set_performance_level(level);
+            // Back under manual the state's profile applies again
+            self.set_power_profile(gpu, new_state);
         }
-        self.apply_dynamic(gpu, new_state, weighted_avg_temperature);
+        self.apply_dynamic(new_state, weighted_avg_temperature);
+        self.anticipate_temperature_rise(gpu);
+        self.govern_temperature(gpu, new_state, current_temperature);
+        self.drive_fan(gpu);
+        self.drive_power_limit(gpu);
         self.state = new_state;
     }
 
-    fn apply_dynamic(&self, gpu: &PolarisGpu<'_>, state: GpuCustomState, temperature: f32) {
-        let fan = gpu.fan();
+    /// Whether Performance is held back because performance_gate_process isn't running
+    fn update_performance_gate(&mut self) -> bool {
+        let gated = match &self.config.performance_gate_process {
+            Some(name) => !process::is_running(name),
+            None => false
+        };
+
+        if gated != self.performance_gated {
+            match &self.config.performance_gate_process {
+                Some(name) if gated => println!("> {} isn't running, staying out of Performance", name),
+                Some(name) => println!("> {} is running, Performance follows the load again", name),
+                None => {}
+            }
+        }
+        self.performance_gated = gated;
+        gated
+    }
+
+    /// Classifies the usage with the short window against the long one, returns it with the short average
+    fn update_usage_load(&mut self) -> (UsageLoad, f64) {
+        let short_samples = self.config.usage_short_window * self.config.samples_per_update;
+        let short = tail_average(self.usage_buffer.iter(), short_samples).unwrap_or(0f64);
+        let long = tail_average(self.usage_buffer.iter(), self.usage_buffer.len()).unwrap_or(0f64);
+
+        let load = if short <= self.config.active_usage_threshold {
+            UsageLoad::Light
+        } else if long > self.config.sustained_usage_threshold {
+            UsageLoad::Sustained
+        } else {
+            UsageLoad::Transient
+        };
+
+        if load != self.usage_load && self.config.filter_transient_load {
+            println!("> Usage is {:?}: {:.1}% short, {:.1}% long", load, short, long);
+        }
+        self.usage_load = load;
+        (load, short)
+    }
+
+    /// Tracks the level forced by performance_level_schedule, true when it changed
+    fn update_schedule(&mut self) -> bool {
+        let scheduled = self.config.performance_level_schedule.as_ref()
+            .and_then(|schedule| schedule::scheduled_level(schedule, schedule::local_minute_of_day()));
+
+        let changed = scheduled != self.scheduled_level;
+        self.scheduled_level = scheduled;
+        changed
+    }
+
+    /// A scheduled level overrides the configured one of any state but Emergency
+    fn performance_level(&self, state: GpuCustomState) -> PerformanceLevel {
+        if state == GpuCustomState::Emergency {
+            return PerformanceLevel::Low;
+        }
+        if let Some(level) = &self.scheduled_level {
+            return level.clone();
+        }
+
         match state {
-            GpuCustomState::Performance =>
-                fan.set_speed(self.performance_curve.get(temperature, CurveInterpolation::Linear)),
-            GpuCustomState::Mining =>
-                fan.set_speed(ClampedPercentage::new(40f64)),
-            _ => {}
+            GpuCustomState::Performance => self.config.performance_performance_level.clone(),
+            GpuCustomState::Mining => self.config.mining_performance_level.clone(),
+            _ => self.config.idle_performance_level.clone()
         }
     }
 
-    fn apply(&self, gpu: &PolarisGpu<'_>, state: GpuCustomState) {
-        println!("> Applying state {:?}", self.state);
+    fn apply_dynamic(&mut self, state: GpuCustomState, temperature: f32) {
+        self.fan_target = Some(match &self.active_curve {
+            Some(curve) => curve.get(temperature, CurveInterpolation::Linear),
+            None => self.fixed_fan_speed(state)
+        });
+    }
+
+    /**
+        Holds the junction temperature at thermal_governor_target in Performance
+        by nudging the power limit down while it's above and back up while below,
+        between idle_power_limit and performance_power_limit. Cards without a
+        junction sensor are governed on the control temperature.
+    **/
+    fn govern_temperature(&mut self, gpu: &PolarisGpu<'_>, state: GpuCustomState, control_temperature: f32) {
+        let target = match self.config.thermal_governor_target {
+            Some(target) if state == GpuCustomState::Performance => target,
+            _ => {
+                // Leaving Performance applies the new state's limit
+                self.governed_power_limit = None;
+                return;
+            }
+        };
+
+        let temperature = self.temperatures.junction.unwrap_or(control_temperature);
+        let maximum = self.config.performance_power_limit;
+        let current = self.governed_power_limit.unwrap_or(maximum);
+        let limit = (current - (temperature - target) * self.config.thermal_governor_gain)
+            .clamp(self.config.idle_power_limit.min(maximum), maximum);
+
+        // The driver only takes whole watts
+        if (limit - current).abs() < 1f32 {
+            return;
+        }
+        if current == maximum {
+            println!("> Junction at {}C, lowering the power limit to hold {}C", temperature, target);
+        }
+
+        self.governed_power_limit = if limit < maximum { Some(limit) } else { None };
+        if !self.read_only {
+            self.set_power_limit(gpu, limit.round());
+        }
+    }
+
+    /**
+        Ramps the fan to full speed while the temperature climbs faster than
+        max_temp_rise_rate, before the smoothed temperature catches up.
+    **/
+    fn anticipate_temperature_rise(&mut self, gpu: &PolarisGpu<'_>) {
+        let slope = self.temperature_slope();
+        let rising_fast = match (self.config.max_temp_rise_rate, slope) {
+            (Some(max_rate), Some(slope)) => slope > max_rate,
+            _ => false
+        };
+
+        if rising_fast {
+            if self.rising_fast {
+                self.fan_target = Some(ClampedPercentage::new(100));
+            } else {
+                self.warn(format!("Temperature rising at {:.1}C/s, ramping up the fan", slope.unwrap_or_default()));
+                // Slewing up would give away the head start on the smoothed temperature
+                self.force_fan_speed(gpu, ClampedPercentage::new(100));
+            }
+        }
+        self.rising_fast = rising_fast;
+    }
 
+    /// Fan speed of states without a curve, Performance always has one
+    fn fixed_fan_speed(&self, state: GpuCustomState) -> ClampedPercentage {
         match state {
-            GpuCustomState::Idle => {
+            GpuCustomState::Idle => ClampedPercentage::new(0),
+            GpuCustomState::CoolOff => self.config.cooloff_fan_speed_max,
+            GpuCustomState::Mining => self.config.mining_fan_speed,
+            GpuCustomState::Performance | GpuCustomState::Emergency => ClampedPercentage::new(100)
+        }
+    }
+
+    /// States without a curve use a fixed fan speed
+    fn fan_curve(&self, state: GpuCustomState) -> Option<&Curve> {
+        match state {
+            GpuCustomState::Idle => self.idle_curve.as_ref(),
+            GpuCustomState::CoolOff => self.cooloff_curve.as_ref(),
+            GpuCustomState::Performance => Some(&self.performance_curve),
+            GpuCustomState::Mining => self.mining_curve.as_ref(),
+            GpuCustomState::Emergency => None
+        }
+    }
+
+    fn set_fan_speed(&mut self, gpu: &PolarisGpu<'_>, speed: ClampedPercentage) {
+        self.fan_target = Some(speed);
+        self.drive_fan(gpu);
+    }
+
+    /// Latest control temperature, read from the card before the first sample
+    fn control_temperature(&self, gpu: &PolarisGpu<'_>) -> Option<f32> {
+        latest(&self.temperature_buffer)
+            .or_else(|| gpu.all_temperatures().control_temperature(self.config.junction_weight))
+    }
+
+    /**
+        Heads the fan to the speed of the state applied, from its curve at the
+        current temperature or its fixed speed. An unknown temperature is
+        taken as the emergency one, the top of the curve.
+    **/
+    fn set_state_fan_speed(&mut self, gpu: &PolarisGpu<'_>, state: GpuCustomState) {
+        let speed = match &self.active_curve {
+            Some(curve) => {
+                let temperature = self.control_temperature(gpu).unwrap_or(self.config.emergency_temperature);
+                curve.get(temperature, CurveInterpolation::Linear)
+            },
+            None => self.fixed_fan_speed(state)
+        };
+        self.set_fan_speed(gpu, speed);
+    }
+
+    /// Writes the fan speed at once, bypassing max_fan_slew_per_sec
+    fn force_fan_speed(&mut self, gpu: &PolarisGpu<'_>, speed: ClampedPercentage) {
+        self.fan_target = Some(speed);
+        self.fan_slew.seed(speed.0, Instant::now());
+        if let Some(fan) = gpu.fan().filter(|_| !self.read_only) {
+            fan.set_speed(speed);
+        }
+    }
+
+    /**
+        Moves the fan towards its target speed, no faster than
+        max_fan_slew_per_sec allows. Only writes when the speed changes,
+        and waits while max_writes_per_minute is used up unless it's going to full speed.
+        With fan_rpm_full_speed the duty is steered towards the target's RPM instead.
+    **/
+    fn drive_fan(&mut self, gpu: &PolarisGpu<'_>) {
+        let fan = match gpu.fan() {
+            Some(fan) if !self.read_only => fan,
+            _ => return
+        };
+
+        if let Some(target) = self.fan_target {
+            // Full speed is what Emergency and a fast temperature rise ask for, that never waits
+            let priority = if target.0 >= 100f64 { WritePriority::Critical } else { WritePriority::Deferrable };
+            if !sysfs::write_permitted(priority) {
+                return;
+            }
+
+            let now = Instant::now();
+            if !self.fan_slew.is_seeded() {
+                self.fan_slew.seed(fan.speed().0, now);
+            }
+
+            let previous = self.fan_slew.last();
+            // Full speed skips the controller, Emergency can't wait for it to ramp up
+            let duty = match (&self.rpm_controller, self.fan_rpm, previous) {
+                (Some(controller), Some(rpm), Some(duty)) if target.0 < 100f64 => controller.next(duty, rpm, target),
+                _ => target.0
+            };
+            let speed = self.fan_slew.next(duty, now);
+            if previous != Some(speed) {
+                fan.set_speed(ClampedPercentage::new(speed));
+            }
+        }
+    }
+
+    /// Takes the fan over from the firmware, if there is a controllable one
+    fn take_fan_control(gpu: &PolarisGpu<'_>) {
+        if let Some(fan) = gpu.fan() {
+            fan.set_mode(FanMode::Manual);
+        }
+    }
+
+    /// Applies the configured startup state, verifying the fan took it
+    pub fn start(&mut self, gpu: &PolarisGpu<'_>) {
+        // The first apply has to know about a scheduled level, it decides whether profiles are written
+        self.update_schedule();
+        match self.config.startup_state {
+            StartupState::Idle => {
+                // A restart while hot mustn't leave the fan stopped, CoolOff spins it until the card cooled down
+                let temperature = self.control_temperature(gpu);
+                let state = if temperature.is_none_or(|temperature| temperature > self.config.cooloff_exit_temperature) {
+                    println!("> Starting at {}C, cooling off before idling",
+                        temperature.map_or("?".to_string(), |temperature| temperature.to_string()));
+                    GpuCustomState::CoolOff
+                } else {
+                    GpuCustomState::Idle
+                };
+                // CoolOff keeps the settings of the state before it, on startup those have to be Idle's
+                if state == GpuCustomState::CoolOff && !self.read_only {
+                    self.apply_idle_settings(gpu, true);
+                }
+                self.apply(gpu, state);
+                self.state = state;
+            },
+            StartupState::Performance => {
+                self.apply(gpu, GpuCustomState::Performance);
+                self.state = GpuCustomState::Performance;
+            },
+            StartupState::Auto => {
+                println!("> Leaving the card as is until the first decision");
+                self.startup_pending = true;
+                return;
+            }
+        }
+        self.verify_fan(gpu);
+    }
 
-                if self.state != GpuCustomState::CoolOff {
-                    gpu.set_pstates(&self.idle_table).expect("Failed to change gpu pstate table");
+    /**
+        Reads the fan mode and speed back after an apply and sets them once
+        more when they don't match, the firmware can keep control of the fan
+        right after startup.
+    **/
+    pub fn verify_fan(&self, gpu: &PolarisGpu<'_>) {
+        let fan = match gpu.fan() {
+            Some(fan) if !self.read_only => fan,
+            _ => return
+        };
+
+        let expected_speed = self.fan_slew.last();
+
+        for retry in [true, false].iter() {
+            let mode = fan.mode();
+            let speed = fan.try_speed();
+            let speed_matches = match (expected_speed, speed) {
+                (Some(expected), Some(speed)) => (expected - speed.0).abs() <= FAN_SPEED_TOLERANCE,
+                (Some(_), None) => false,
+                (None, _) => true
+            };
+
+            if mode == FanMode::Manual && speed_matches {
+                return;
+            }
+
+            self.warn(format!("Fan reads {:?} at {}, expected Manual at {}", mode,
+                speed.map_or("?".to_string(), |speed| speed.to_string()),
+                expected_speed.map_or("?".to_string(), |speed| ClampedPercentage::new(speed).to_string())));
+
+            if *retry {
+                fan.set_mode(FanMode::Manual);
+                if let Some(expected) = expected_speed {
+                    fan.set_speed(ClampedPercentage::new(expected));
                 }
+            }
+        }
+    }
+
+    /**
+        Hands the card back to the driver. The fan goes back to the firmware
+        first, so the card is never left warm with a stopped fan while the
+        rest is restored.
+    **/
+    pub fn shutdown(&self, gpu: &PolarisGpu<'_>, power_limit: f32) {
+        if self.read_only {
+            return;
+        }
+
+        sysfs::audit("Shutting down");
+        if let Some(fan) = gpu.fan() {
+            fan.set_mode(FanMode::Auto);
+            thread::sleep(SHUTDOWN_FAN_SETTLE_TIME);
+        }
+
+        // Every step is tried on its own, one failing mustn't leave the rest tuned
+        self.write_power_limit(gpu, power_limit);
+        // The driver only takes the default profile under manual, the level is handed back after it
+        match gpu.try_set_performance_level(PerformanceLevel::Manual)
+            .and_then(|_| gpu.try_set_power_profile_mode(1)) {
+            Ok(_) => {},
+            Err(err) => self.warn(format!("Failed to restore the default power profile: {}", err))
+        }
+        if let Err(err) = gpu.try_set_performance_level(PerformanceLevel::Auto) {
+            self.warn(format!("Failed to restore the auto performance level: {}", err));
+        }
+        if let Err(err) = gpu.try_reset_pstates() {
+            self.warn(format!("Failed to reset the pstate table: {}", err));
+        }
+    }
+
+    /// Applies Emergency outside of step, it's left like any other once the temperature allows
+    pub fn enter_safe_state(&mut self, gpu: &PolarisGpu<'_>) {
+        if self.state != GpuCustomState::Emergency {
+            self.emit(Event::Transition { from: self.state, to: GpuCustomState::Emergency });
+            self.emergency_entered = Some(Instant::now());
+        }
+        self.apply(gpu, GpuCustomState::Emergency);
+        self.state = GpuCustomState::Emergency;
+    }
+
+    /// Runs after the safe settings were applied, so a failing hook can't prevent them
+    fn on_emergency(&self, temperature: f32) {
+        self.warn(format!("Emergency, temperature reached {}C", temperature));
+        self.emit(Event::Emergency { temperature });
+
+        if let Some(command) = &self.config.on_emergency {
+            hook::run(command, &[temperature.to_string()],
+                &[("SENTINEL_TEMPERATURE", temperature.to_string())]);
+        }
+    }
+
+    /// Heads the power limit towards wattage, as fast as power_limit_rise_rate and power_limit_fall_rate allow
+    fn set_power_limit(&mut self, gpu: &PolarisGpu<'_>, wattage: f32) {
+        self.power_limit_target = Some(wattage);
+        self.drive_power_limit(gpu);
+    }
+
+    /// Writes the power limit at once, bypassing the rates
+    fn force_power_limit(&mut self, gpu: &PolarisGpu<'_>, wattage: f32) {
+        self.power_limit_target = Some(wattage);
+        self.power_limit_slew.seed(wattage as f64, Instant::now());
+        self.write_power_limit(gpu, wattage);
+    }
+
+    /// Moves the power limit a step towards its target, the driver only takes whole watts
+    fn drive_power_limit(&mut self, gpu: &PolarisGpu<'_>) {
+        let target = match self.power_limit_target {
+            Some(target) if !self.read_only => target,
+            _ => return
+        };
+
+        let now = Instant::now();
+        let previous = self.power_limit_slew.last();
+        if previous.is_none() {
+            if let Some(limit) = gpu.try_power_limit() {
+                self.power_limit_slew.seed(limit as f64, now);
+            }
+        }
+
+        let limit = self.power_limit_slew.next(target as f64, now).round() as f32;
+        if previous.map(|previous| previous.round() as f32) != Some(limit) {
+            self.write_power_limit(gpu, limit);
+        }
+    }
+
+    fn write_power_limit(&self, gpu: &PolarisGpu<'_>, wattage: f32) {
+        let retry_delay = time::Duration::from_secs_f32(self.config.power_limit_retry_delay);
 
-                gpu.set_performance_level(PerformanceLevel::Manual);
+        match gpu.set_power_limit_verified(wattage, retry_delay) {
+            Ok(_) => {},
+            Err(PowerLimitError::OutOfRange(range)) =>
+                self.warn(format!("Power limit {}W is outside of [{}, {}]", wattage, range.start(), range.end())),
+            Err(PowerLimitError::NotApplied(limit)) =>
+                self.warn(format!("Power limit {}W didn't stick, the card reports {}", wattage,
+                    limit.map_or("?".to_string(), |limit| format!("{}W", limit))))
+        }
+    }
+
+    fn set_pstates(&self, gpu: &PolarisGpu<'_>, table: &PolarisGpuTable) {
+        match gpu.apply_table_safely(table, self.config.pstate_clock_step, self.config.pstate_voltage_step) {
+            Ok(_) => {},
+            Err(OverclockError::VoltageNotApplied) =>
+                self.warn("The card rejected some pstate voltages, only their clocks were applied".to_string()),
+            Err(OverclockError::ParseFailed) => {
+                self.warn("The gpu pstate table couldn't be parsed, clocks are left alone".to_string());
+                report_unparsed_table(gpu, &self.config);
+            },
+            Err(err) => self.warn(format!("Failed to change gpu pstate table: {:?}", err))
+        }
+    }
+
+    /// Everything of Idle but the fan, the table is only written with write_table
+    fn apply_idle_settings(&mut self, gpu: &PolarisGpu<'_>, write_table: bool) {
+        if write_table {
+            self.set_pstates(gpu, &self.idle_table);
+        }
+
+        gpu.set_performance_level(self.performance_level(GpuCustomState::Idle));
+        self.set_power_limit(gpu, self.config.idle_power_limit);
+        self.set_power_profile(gpu, GpuCustomState::Idle);
+        self.set_pcie_level(gpu, GpuCustomState::Idle);
+    }
+
+    fn apply(&mut self, gpu: &PolarisGpu<'_>, state: GpuCustomState) {
+        self.active_curve = self.fan_curve(state).cloned();
+
+        // The decision and fan target are still computed, only the writes are left out
+        if self.read_only {
+            println!("> Would apply state {:?}", state);
+            return;
+        }
+        println!("> Applying state {:?}", self.state);
+        sysfs::audit(&format!("Applying state {:?}", state));
+
+        match state {
+            GpuCustomState::Idle => {
+                // CoolOff runs on the idle table already
+                self.apply_idle_settings(gpu, self.state != GpuCustomState::CoolOff);
 
-                gpu.fan().set_mode(FanMode::Manual);
-                gpu.fan().set_speed(ClampedPercentage::new(0));
-                gpu.set_power_limit(30f32);
-                gpu.set_power_profile_mode(2);
+                Self::take_fan_control(gpu);
+                self.set_state_fan_speed(gpu, state);
             },
             GpuCustomState::Performance => {
-                gpu.set_pstates(&self.performance_table).expect("Failed to change gpu pstate table");
+                self.set_pstates(gpu, &self.performance_table);
 
-                gpu.set_performance_level(PerformanceLevel::Auto);
+                gpu.set_performance_level(self.performance_level(state));
 
-                gpu.fan().set_mode(FanMode::Manual);
-                gpu.set_power_limit(150f32);
+                Self::take_fan_control(gpu);
+                self.set_state_fan_speed(gpu, state);
+                self.set_power_limit(gpu, self.config.performance_power_limit);
+                self.set_power_profile(gpu, state);
+                self.set_pcie_level(gpu, state);
             },
             GpuCustomState::Mining => {
-                gpu.set_pstates(&self.mining_table).expect("Failed to change gpu pstate table");
+                self.set_pstates(gpu, &self.mining_table);
 
-                gpu.set_performance_level(PerformanceLevel::Manual);
-                gpu.set_power_profile_mode(5);
+                gpu.set_performance_level(self.performance_level(state));
+                self.set_power_profile(gpu, state);
 
-                gpu.fan().set_mode(FanMode::Manual);
-                gpu.set_power_limit(110f32);
+                Self::take_fan_control(gpu);
+                self.set_state_fan_speed(gpu, state);
+                self.set_power_limit(gpu, self.config.mining_power_limit);
             },
             GpuCustomState::CoolOff => {
-                gpu.fan().set_mode(FanMode::Manual);
-                gpu.fan().set_speed(ClampedPercentage::new(35));
+                Self::take_fan_control(gpu);
+                self.set_state_fan_speed(gpu, state);
+            },
+            GpuCustomState::Emergency => {
+                Self::take_fan_control(gpu);
+                // An overheating card can't wait for the fan to slew up
+                self.force_fan_speed(gpu, self.fixed_fan_speed(state));
+
+                self.set_pstates(gpu, &self.idle_table);
+                gpu.set_performance_level(self.performance_level(state));
+                self.force_power_limit(gpu, self.config.idle_power_limit);
             }
         }
     }
 }
 
-fn create_idle_table<'a>(table: &'a PolarisGpuTable) -> PolarisGpuTable {
+fn create_idle_table(table: &PolarisGpuTable, memory_range_floor: bool) -> Result<PolarisGpuTable, StateInvalidReason> {
     let mut idle_table: PolarisGpuTable = table.clone();
 
     for part in [Part::Core, Part::Memory].iter() {
-        let lowest_pstate = table.get_state(*part, 0).expect("Failed to get lowest state");
+        let lowest_pstate = match part {
+            Part::Memory if memory_range_floor => table.range_floor_state(*part)?,
+            _ => table.get_state(*part, 0).ok_or(StateInvalidReason::InvalidIndex)?
+        };
         for idx in 0..idle_table.states(*part).len() {
-            idle_table.set_state(*part, idx, lowest_pstate).unwrap();
+            idle_table.set_state(*part, idx, lowest_pstate)?;
         }
     }
-    idle_table
+    Ok(idle_table)
 }
 
-fn create_performance_table<'a>(table: &'a PolarisGpuTable,
+fn create_performance_table(table: &PolarisGpuTable,
     highest_core_state: &PolarisGpuState,
     highest_memory_state: &PolarisGpuState,
-    fixed_memory: bool)
--> PolarisGpuTable {
+    fixed_memory: bool,
+    memory_clock: Option<u32>)
+-> Result<PolarisGpuTable, StateInvalidReason> {
     let mut perf_table = table.clone();
 
-    let dynamic_parts = if fixed_memory {
+    // Slightly too high requested states are pulled into the card's ranges instead of failing
+    let clamp = |part: Part, state: &PolarisGpuState| {
+        let clamped = table.clamp_state(part, *state);
+        if clamped != *state {
+            println!("Clamped highest {:?} state {} to {}", part, state, clamped);
+        }
+        clamped
+    };
+    let highest_core_state = &clamp(Part::Core, highest_core_state);
+    let highest_memory_state = &clamp(Part::Memory, highest_memory_state);
+
+    // Memory is either pinned to the state nearest the requested clock, to the highest one, or left dynamic
+    let pinned_memory_state = match memory_clock {
+        Some(clock) => Some(table.nearest_state(Part::Memory, clock).ok_or(StateInvalidReason::InvalidIndex)?),
+        None if fixed_memory => Some(*highest_memory_state),
+        None => None
+    };
+
+    let dynamic_parts = if pinned_memory_state.is_some() {
         vec![Part::Core]
     } else {
         vec![Part::Core, Part::Memory]
@@ -303,10 +1384,10 @@ fn create_performance_table<'a>(table: &'a PolarisGpuTable,
         };
 
         let states = perf_table.states(*part).clone();
-        let count = states.len();
+        let last_index = perf_table.last_state_index(*part)?;
 
         for (idx, state) in states.iter().enumerate() {
-            let mut new_state: PolarisGpuState = state.clone();
+            let mut new_state: PolarisGpuState = *state;
             if new_state.voltage > highest_state.voltage {
                 new_state.voltage = highest_state.voltage;
             }
@@ -314,81 +1395,457 @@ fn create_performance_table<'a>(table: &'a PolarisGpuTable,
                 new_state.clock = highest_state.clock;
             }
 
-            perf_table.set_state(*part, idx, new_state).unwrap();
+            perf_table.set_state(*part, idx, new_state)?;
         }
-        perf_table.set_state(*part, count - 1, *highest_state).unwrap();
+        perf_table.set_state(*part, last_index, *highest_state)?;
     }
 
-    if fixed_memory {
+    if let Some(memory_state) = pinned_memory_state {
         for (idx, _) in table.states(Part::Memory).iter().enumerate() {
-            perf_table.set_state(Part::Memory, idx, *highest_memory_state).unwrap();
+            perf_table.set_state(Part::Memory, idx, memory_state)?;
+        }
+    }
+
+    Ok(perf_table)
+}
+
+/**
+    Shows the raw table the parser choked on, and writes it to unparsed_table_dump
+    so it can be attached to a bug report.
+**/
+fn report_unparsed_table(gpu: &PolarisGpu<'_>, config: &Config) {
+    let data = gpu.raw_pstates().unwrap_or_default();
+    println!("  Unparsed table:\n{}", data.trim_end());
+
+    if let Some(path) = &config.unparsed_table_dump {
+        match std::fs::write(path, &data) {
+            Ok(_) => println!("  Unparsed table written to {:?}", path),
+            Err(err) => println!("! Failed to write the unparsed table to {:?}: {}", path, err)
+        }
+    }
+}
+
+/**
+    The configured CoolOff curve, or one rising from cooloff_fan_speed_min at the exit
+    temperature to cooloff_fan_speed_max at the enter one, so a barely warm card cools quietly.
+**/
+fn cooloff_curve(config: &Config) -> Curve {
+    let points = config.cooloff_fan_curve.clone().unwrap_or_else(|| vec![
+        CurvePoint { temperature: config.cooloff_exit_temperature.round() as u32, fan_speed: config.cooloff_fan_speed_min },
+        CurvePoint { temperature: config.cooloff_enter_temperature.round() as u32, fan_speed: config.cooloff_fan_speed_max }
+    ]);
+    Curve::new(points)
+}
+
+/// Limits relative config values are resolved against
+fn card_limits(gpu: &PolarisGpu<'_>) -> CardLimits {
+    CardLimits {
+        crit: gpu.critical_temperature(),
+        sclk_max: gpu.clock_range(Part::Core).map(|range| *range.end()),
+        mclk_max: gpu.clock_range(Part::Memory).map(|range| *range.end())
+    }
+}
+
+/// Idle, Performance and Mining tables derived from the card's table and the config
+fn create_state_tables(gpu_table: Option<&PolarisGpuTable>, config: &Config, voltage_control: bool)
+-> Result<(PolarisGpuTable, PolarisGpuTable, PolarisGpuTable), StateInvalidReason> {
+    // Read-only mode runs without a table, it never writes one anyway
+    let gpu_table = match gpu_table {
+        Some(table) => table,
+        None => return Ok((PolarisGpuTable::empty(), PolarisGpuTable::empty(), PolarisGpuTable::empty()))
+    };
+    let mut idle_table: PolarisGpuTable = create_idle_table(gpu_table, config.idle_memory_range_floor)?;
+    let mut performance_table: PolarisGpuTable = create_performance_table(gpu_table,
+        &PolarisGpuState { clock: 1250, voltage: 1025 },
+        &PolarisGpuState { clock: 1700, voltage: 900 },
+        false,
+        config.performance_memory_clock)?;
+    // Mining favours memory, so it's pinned to the highest state unless configured otherwise
+    let highest_memory_state = gpu_table.get_state(Part::Memory, gpu_table.last_state_index(Part::Memory)?)
+        .ok_or(StateInvalidReason::InvalidIndex)?;
+    let mut mining_table: PolarisGpuTable = create_performance_table(gpu_table,
+        &PolarisGpuState { clock: config.mining_core_clock, voltage: config.mining_core_voltage },
+        &highest_memory_state,
+        true,
+        config.mining_memory_clock)?;
+
+    // Idle, CoolOff and Emergency share the idle table, Mining already holds memory at its own fixed clock
+    if config.pin_memory_always {
+        let pinned = performance_table.get_state(Part::Memory, performance_table.last_state_index(Part::Memory)?)
+            .ok_or(StateInvalidReason::InvalidIndex)?;
+        for table in [&mut idle_table, &mut performance_table].iter_mut() {
+            for idx in 0..table.states(Part::Memory).len() {
+                table.set_state(Part::Memory, idx, pinned)?;
+            }
         }
     }
 
-    perf_table
+    for table in [&mut idle_table, &mut performance_table, &mut mining_table].iter_mut() {
+        apply_safe_clock_limits(table, config);
+        if !voltage_control {
+            table.keep_voltages(gpu_table);
+        }
+    }
+
+    Ok((idle_table, performance_table, mining_table))
+}
+
+/// Clamps the table to the configured safe clocks, which can be narrower than the advertised range
+fn apply_safe_clock_limits(table: &mut PolarisGpuTable, config: &Config) {
+    if let Some(max_clock) = config.safe_sclk_max {
+        table.limit_clocks(Part::Core, max_clock);
+    }
+    if let Some(max_clock) = config.safe_mclk_max {
+        table.limit_clocks(Part::Memory, max_clock);
+    }
+}
+
+/// Reloads the --fan-curves file once it was modified since last_modified, the rest of the config is left alone
+fn reload_fan_curves(state_machine: &mut GpuStateMachine, path: &Path, last_modified: &mut Option<SystemTime>) {
+    // A file being replaced by an editor can be briefly missing, it's picked up on a later step
+    let modified = match std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified,
+        Err(_) => return
+    };
+    if *last_modified == Some(modified) {
+        return;
+    }
+    *last_modified = Some(modified);
+
+    match FanCurves::load(path).and_then(|curves| state_machine.set_fan_curves(&curves)) {
+        Ok(()) => println!("> Loaded fan curves from {:?}", path),
+        Err(err) => println!("! Keeping the previous fan curves, {:?} is invalid: {}", path, err)
+    }
+}
+
+/// Puts the card in Emergency after a caught panic, handing the fan to the firmware if even that panics
+fn recover_from_panic(state_machine: &mut GpuStateMachine, gpu: &PolarisGpu<'_>) {
+    println!("! The loop panicked, applying Emergency");
+    if panic::catch_unwind(panic::AssertUnwindSafe(|| state_machine.enter_safe_state(gpu))).is_err() {
+        println!("! Applying Emergency panicked too, handing the fan to the firmware");
+        if let Some(fan) = gpu.fan() {
+            let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| fan.set_mode(FanMode::Auto)));
+        }
+    }
 }
 
+/// Keeps the table applied, reapplying it whenever the driver reverts it
+fn guard_table(gpu: &PolarisGpu<'_>, table: &PolarisGpuTable, interval: time::Duration, term: &AtomicBool) {
+    while !term.load(Ordering::Relaxed) {
+        if !gpu.pstates_match(table) {
+            println!("> Table drifted, reapplying");
+            if let Err(err) = gpu.set_pstates_verified(table) {
+                println!("! Failed to reapply table: {:?}", err);
+            }
+        }
+
+        thread::sleep(interval);
+    }
+}
 
 fn main() {
-    let rx570 = PolarisGpu::new("RX 570", Path::new("/sys/class/drm/card0/device/"));
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    if args.print_defaults {
+        print!("{}", Config::default().to_commented_toml());
+        return;
+    }
+
+    if let Some(path) = &args.check_config {
+        match Config::load(path) {
+            Ok(config) if !config.relative.is_empty() =>
+                println!("{:?} is valid, values relative to the card are checked once resolved on startup", path),
+            Ok(_) => println!("{:?} is valid", path),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if cfg!(not(feature = "sqlite")) && args.db.is_some() {
+        eprintln!("--db requires building with the sqlite feature");
+        std::process::exit(2);
+    }
+
+    let base_config = match &args.config {
+        Some(path) => Config::load(path).unwrap_or_else(|err| panic!("{}", err)),
+        None => Config::default()
+    };
+    let mut profile = args.profile.clone();
+    let config = match &profile {
+        Some(name) => base_config.with_profile(name).unwrap_or_else(|err| panic!("{}", err)),
+        None => base_config.clone()
+    };
+
+    // Everything else is relative to the card, so a mock tree can stand in for /sys
+    let sysfs_root = args.sysfs_root.clone().unwrap_or_else(|| PathBuf::from("/sys"));
+    let sysfs_dir = match &args.render_node {
+        Some(render_node) => card_dir_for_render_node(&sysfs_root, render_node).unwrap_or_else(|| {
+            eprintln!("No card found behind the render node {:?}", render_node);
+            std::process::exit(1);
+        }),
+        None => sysfs_root.join("class/drm/card0/device")
+    };
+    let name = config.gpu_name.clone()
+        .or_else(|| PolarisGpu::detect_name_at(&sysfs_dir))
+        .unwrap_or_else(|| "AMD GPU".to_string());
+    let mut rx570 = PolarisGpu::new(&name, &sysfs_dir, config.hwmon_name.as_deref());
+    rx570.set_temperature_offsets(TemperatureOffsets {
+        edge: config.edge_offset,
+        junction: config.junction_offset,
+        memory: config.memory_offset
+    });
+    if let Some(rpm) = config.noise_reference_rpm {
+        rx570.set_noise_reference(NoiseReference { rpm, dba: config.noise_reference_dba });
+    }
+    if config.keep_sysfs_files_open {
+        rx570.keep_files_open();
+    }
+    let limits = card_limits(&rx570);
+    let config = config.resolve(&limits).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
     let term = Arc::new(AtomicBool::new(false));
 
     signal_hook::flag::register(signal_hook::SIGTERM, Arc::clone(&term)).expect("Failed to register hook for SIGTERM");
     signal_hook::flag::register(signal_hook::SIGINT, Arc::clone(&term)).expect("Failed to register hook for SIGINT");
+    let next_profile = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::SIGUSR1, Arc::clone(&next_profile)).expect("Failed to register hook for SIGUSR1");
+
+    if args.caps {
+        println!("{}\n{}", rx570.name, rx570.capabilities());
+        if let Some(table) = rx570.read_pstates() {
+            let join = |headroom: Vec<u32>| headroom.iter().map(u32::to_string).collect::<Vec<_>>().join(", ");
+            println!("Undervolt headroom (mV above {}mV):", table.voltage_range().start());
+            println!("  Core:   {}", join(table.voltage_headroom(Part::Core)));
+            println!("  Memory: {}", join(table.voltage_headroom(Part::Memory)));
+        }
+        return;
+    }
+
+    if let Some(path) = &args.save_state {
+        let state = serde_json::to_string_pretty(&rx570.capture_state()).expect("Failed to serialize the card state");
+        std::fs::write(path, state).unwrap_or_else(|err| panic!("Failed to write {:?}: {}", path, err));
+        println!("Saved the card state to {:?}", path);
+        return;
+    }
+    if let Some(path) = &args.restore_state {
+        if args.read_only {
+            eprintln!("Restoring the card state writes to it, drop --read-only");
+            std::process::exit(2);
+        }
+        let data = std::fs::read_to_string(path).unwrap_or_else(|err| panic!("Failed to read {:?}: {}", path, err));
+        let state: card_state::CardState = serde_json::from_str(&data).unwrap_or_else(|err| panic!("Invalid card state {:?}: {}", path, err));
+        if let Err(err) = rx570.restore_state(&state) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        println!("Restored the card state from {:?}", path);
+        return;
+    }
+
+    if config.audit && !args.read_only {
+        if let Err(err) = sysfs::enable_audit(config.audit_file.as_ref()) {
+            println!("! Failed to open the audit log {:?}: {}", config.audit_file, err);
+        }
+    }
+    if let Some(per_minute) = config.max_writes_per_minute {
+        sysfs::limit_writes(per_minute);
+    }
 
-    let update_interval = time::Duration::from_secs_f32(1f32);
-    let gathers_per_update = 2;
+    // Missing required nodes abort in preflight, the others only disable a feature
+    print!("{}", rx570.validate_nodes(args.read_only));
 
-    let sleep_time = update_interval.div(gathers_per_update.try_into().unwrap());
+    if rx570.fan().is_none() {
+        println!("! No controllable fan found, the firmware keeps managing it");
+    }
 
-    let mut gathers = 0;
+    if let Err(errors) = GpuStateMachine::preflight(&rx570, &config, args.read_only) {
+        eprintln!("Preflight checks failed, leaving the card alone:");
+        for err in errors.iter() {
+            eprintln!(" - {}", err);
+        }
+        std::process::exit(1);
+    }
 
-    let old_power_limit = rx570.power_limit();
+    if args.read_only && args.guard_table.is_some() {
+        eprintln!("--guard-table writes the table, it can't be combined with --read-only");
+        std::process::exit(2);
+    }
 
-    let curve = Curve::new(vec![
-        CurvePoint { temperature: 50, fan_speed: ClampedPercentage::new(0f64) },
-        CurvePoint { temperature: 55, fan_speed: ClampedPercentage::new(30f64) },
-        CurvePoint { temperature: 65, fan_speed: ClampedPercentage::new(35f64) },
-        CurvePoint { temperature: 75, fan_speed: ClampedPercentage::new(45f64) },
-        CurvePoint { temperature: 80, fan_speed: ClampedPercentage::new(70f64) }
-    ]);
+    if let Some(path) = &args.guard_table {
+        let data = sysfs::try_read_string_from_file(path).expect("Failed to read table file");
+        let mut table = PolarisGpuTable::try_parse(&data).expect("Failed to parse table file");
+        apply_safe_clock_limits(&mut table, &config);
 
-    let gpu_table: PolarisGpuTable = rx570.read_pstates().expect("Failed to read gpu pstates");
-    let idle_table: PolarisGpuTable = create_idle_table(&gpu_table);
-    let performance_table: PolarisGpuTable = create_performance_table(&gpu_table,
-        &PolarisGpuState { clock: 1250, voltage: 1025 },
-        &PolarisGpuState { clock: 1700, voltage: 900 },
-        false);
-    let mining_table: PolarisGpuTable = create_performance_table(&gpu_table,
-        &PolarisGpuState { clock: 1000, voltage: 800 },
-        &PolarisGpuState { clock: 1800, voltage: 800 },
-        false);
+        println!("Guarding table\r\n{}", table);
+        guard_table(&rx570, &table, time::Duration::from_secs_f32(config.guard_table_interval), &term);
+        return;
+    }
+
+    let mut update_interval = time::Duration::from_secs_f32(config.update_interval);
+    let gathers_per_update = config.samples_per_update;
+
+    let mut sleep_time = update_interval.div(gathers_per_update.try_into().unwrap());
+
+    // Only missing in read-only mode, where shutdown writes nothing
+    let old_power_limit = rx570.try_power_limit().unwrap_or_default();
+
+    if config.reset_on_startup && !args.read_only {
+        // Derive the tables from firmware defaults rather than whatever a previous instance left behind
+        if let Err(err) = rx570.reset_pstates_and_wait(PSTATE_RESET_TIMEOUT) {
+            println!("! The pstate table didn't settle after the reset: {:?}", err);
+        }
+    }
+
+    let gpu_table: Option<PolarisGpuTable> = match rx570.try_read_pstates() {
+        Ok(table) => Some(table),
+        Err(OverclockError::Disabled) if args.read_only => {
+            println!("! The card has no pstate table, monitoring without state tables");
+            None
+        },
+        Err(OverclockError::ParseFailed) => {
+            report_unparsed_table(&rx570, &config);
+            panic!("Unsupported gpu pstate table format, please report it along with the table above");
+        },
+        Err(err) => panic!("Failed to read gpu pstates: {:?}", err)
+    };
+
+    // The probe writes a state back, so read-only mode assumes voltage control works
+    let voltage_control = args.read_only || rx570.supports_voltage_control();
+    if !voltage_control {
+        println!("Voltage control is not supported, only clocks will be changed");
+    }
+
+    let (idle_table, performance_table, mining_table) = create_state_tables(gpu_table.as_ref(), &config, voltage_control)
+        .expect("Failed to create state tables");
 
     println!("Idle table\r\n{}\r\nPerformance\r\n{}\r\nMining {}", idle_table, performance_table, mining_table);
 
-    let mut state_machine = GpuStateMachine::new(gathers_per_update, idle_table, performance_table, mining_table, curve);
-    state_machine.apply(&rx570, GpuCustomState::Idle);
+    let display_refresh_interval = time::Duration::from_secs_f32(config.display_refresh_interval);
+    #[cfg(feature = "sqlite")]
+    let mut telemetry = args.db.as_ref()
+        .map(|path| telemetry_db::TelemetryDb::open(path, config.db_flush_rows).expect("Failed to open telemetry database"));
+    if config.failsafe_firmware_curve && !args.read_only && !rx570.set_failsafe_firmware_curve(&config.fan_curve) {
+        println!("! The card has no firmware fan curve, a SIGKILL leaves the fan at its last speed");
+    }
+    let mut state_machine = GpuStateMachine::new(gathers_per_update, idle_table, performance_table, mining_table, config, args.read_only);
+    state_machine.resolve_power_profiles(&rx570);
+    state_machine.resolve_pcie_switching(&rx570);
+    state_machine.start(&rx570);
+    println!("{}", state_machine.window_summary());
+
+    let snapshot: monitor::SharedSnapshot = Arc::new(Mutex::new(None));
+    let display = if args.monitor {
+        Some(monitor::spawn(rx570.name.to_string(), Arc::clone(&snapshot), display_refresh_interval, Arc::clone(&term)))
+    } else {
+        None
+    };
+
+    let mut next_step = Instant::now();
+    let mut fan_curves_modified = None;
+    let mut last_logged: Option<GpuSnapshot> = None;
+    if let Some(path) = args.fan_curves.as_deref().filter(|path| !path.is_file()) {
+        println!("! {:?} doesn't exist yet, its fan curves are loaded once it does", path);
+    }
 
     while !term.load(Ordering::Relaxed) {
 
-        state_machine.update(&rx570);
+        if next_profile.swap(false, Ordering::Relaxed) {
+            // Cycles through the profiles in name order and back to the base config
+            let names = base_config.profile_names();
+            profile = match &profile {
+                None => names.first().map(|name| name.to_string()),
+                Some(current) => names.iter().skip_while(|name| **name != current).nth(1).map(|name| name.to_string())
+            };
+
+            let switched = match &profile {
+                Some(name) => base_config.with_profile(name),
+                None => Ok(base_config.clone())
+            }.and_then(|config| config.resolve(&limits));
+            match switched {
+                Ok(config) => match create_state_tables(gpu_table.as_ref(), &config, voltage_control) {
+                    Ok((idle_table, performance_table, mining_table)) => {
+                        println!("> Switching to profile {}", profile.as_deref().unwrap_or("default"));
+                        update_interval = time::Duration::from_secs_f32(config.update_interval);
+                        sleep_time = update_interval.div(config.samples_per_update.try_into().unwrap());
+                        state_machine.switch_config(&rx570, config, idle_table, performance_table, mining_table);
+                        // The --fan-curves file is laid over the new profile again
+                        fan_curves_modified = None;
+                        println!("{}", state_machine.window_summary());
+                    },
+                    Err(err) => println!("! Failed to create tables for profile: {:?}", err)
+                },
+                Err(err) => println!("! {}", err)
+            }
+        }
+
+        let catch_panics = state_machine.config.catch_loop_panics;
+        let mut iteration = || {
+            state_machine.update(&rx570);
+            *snapshot.lock().expect("Snapshot lock poisoned") = Some(state_machine.snapshot());
+
+            let now = Instant::now();
+            if now >= next_step {
+                // Steps stay on the interval grid, unless a stall put them more than an interval behind
+                next_step += update_interval;
+                if next_step <= now {
+                    next_step = now + update_interval;
+                }
 
-        if gathers % gathers_per_update == 0 {
+                // With log_on_change the line is only printed when it differs from the last printed one
+                let changed = !state_machine.config.log_on_change || {
+                    let logged = state_machine.snapshot();
+                    let changed = last_logged.as_ref()
+                        .is_none_or(|last| logged.worth_logging(last, state_machine.config.log_fan_speed_step));
+                    if changed {
+                        last_logged = Some(logged);
+                    }
+                    changed
+                };
+                if !args.monitor && changed {
+                    println!("{} temperature: {}C, fan: {} ({} RPM), state: {:?}{}", rx570.name,
+                        rx570.read_sensor(TemperatureSensor::Edge).map_or("?".to_string(), |temperature| temperature.to_string()),
+                        rx570.fan().and_then(|fan| fan.try_speed()).map_or("?".to_string(), |speed| speed.to_string()),
+                        state_machine.fan_rpm().map_or("?".to_string(), |rpm| rpm.to_string()),
+                        state_machine.state(),
+                        state_machine.transition_block_reason().map_or("".to_string(), |reason| format!(" ({})", reason)));
+                }
 
-            println!("{} temperature: {}C, fan: {}, state: {:?}", rx570.name,
-                rx570.temperature(), rx570.fan().speed(), state_machine.state());
+                if let Some(path) = &args.fan_curves {
+                    reload_fan_curves(&mut state_machine, path, &mut fan_curves_modified);
+                }
+                state_machine.step(&rx570);
 
-            state_machine.step(&rx570);
+                #[cfg(feature = "sqlite")]
+                if let Some(db) = telemetry.as_mut() {
+                    if let Err(err) = db.insert(&state_machine.snapshot()) {
+                        println!("! Failed to record telemetry: {}", err);
+                    }
+                }
+            }
+        };
+        if !catch_panics {
+            iteration();
+        } else if panic::catch_unwind(panic::AssertUnwindSafe(&mut iteration)).is_err() {
+            recover_from_panic(&mut state_machine, &rx570);
         }
 
         thread::sleep(sleep_time);
-        gathers += 1;
     }
 
-    rx570.fan().set_mode(FanMode::Auto);
-    rx570.set_power_profile_mode(1);
-    rx570.set_power_limit(old_power_limit);
-    rx570.set_performance_level(PerformanceLevel::Auto);
-    rx570.reset_pstates();
+    if let Some(display) = display {
+        display.join().expect("Monitor thread panicked");
+    }
+
+    state_machine.shutdown(&rx570, old_power_limit);
     println!("Qutting...");
 }
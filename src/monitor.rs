@@ -0,0 +1,27 @@
+use crate::snapshot::GpuSnapshot;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+pub type SharedSnapshot = Arc<Mutex<Option<GpuSnapshot>>>;
+
+/**
+    Redraws the latest snapshot on its own timer, independent of how often
+    the control loop samples and decides.
+**/
+pub fn spawn(name: String, snapshot: SharedSnapshot, refresh_interval: Duration, term: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        while !term.load(Ordering::Relaxed) {
+            let latest = snapshot.lock().expect("Snapshot lock poisoned").clone();
+
+            if let Some(latest) = latest {
+                // Clear the screen and move the cursor home
+                print!("\x1b[2J\x1b[H{}\n{}\n", name, latest);
+            }
+
+            thread::sleep(refresh_interval);
+        }
+    })
+}
@@ -1,4 +1,6 @@
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Serialized with the same names the driver uses in power_dpm_force_performance_level
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PerformanceLevel {
     Auto,
     Low,
@@ -13,6 +15,7 @@ pub enum PerformanceLevel {
 pub trait ControllablePerformanceLevel {
     fn performance_level(&self) -> PerformanceLevel;
     fn set_performance_level(&self, level: PerformanceLevel);
+    fn try_set_performance_level(&self, level: PerformanceLevel) -> Result<(), std::io::Error>;
 }
 
 impl std::fmt::Display for PerformanceLevel {
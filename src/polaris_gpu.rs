@@ -1,4 +1,8 @@
 use crate::clamped_percentage::ClampedPercentage;
+use crate::card_state::{CardState, RestoreError};
+use crate::curve::CurvePoint;
+use crate::fan::{FanControl, FanMode};
+use crate::performance_level::{ControllablePerformanceLevel, PerformanceLevel};
 use crate::sysfs;
 use crate::polaris_gpu_fan;
 use crate::polaris_gpu_table;
@@ -9,6 +13,8 @@ use std::path::Path;
 use std::ops::RangeInclusive;
 use std::path::PathBuf;
 use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
 use polaris_gpu_fan::PolarisGpuFan;
 use polaris_gpu_table::PolarisGpuTable;
 use amdgpu_performance_level::AmdGpuSysfsPerformanceLevel;
@@ -18,7 +24,43 @@ pub struct PolarisGpu<'a> {
     pub name: &'a str,
     sysfs_dir: PathBuf,
     hwmon_dir: PathBuf,
-    fan: PolarisGpuFan
+    temperature_offsets: TemperatureOffsets,
+    /// None when no reference point is configured, see estimated_noise
+    noise_reference: Option<NoiseReference>,
+    /// None when no pwm is controllable, the firmware keeps managing the fan
+    fan: Option<PolarisGpuFan>,
+    /// Keeps the sensor files open between reads when set, see keep_files_open
+    reader: Option<sysfs::SysfsReader>
+}
+
+/// Calibration added to each sensor's reading, in C
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TemperatureOffsets {
+    pub edge: f32,
+    pub junction: f32,
+    pub memory: f32
+}
+
+/// A fan speed (RPM) and the loudness (dBA) measured at it, anchors estimated_noise
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseReference {
+    pub rpm: u32,
+    pub dba: f32
+}
+
+impl NoiseReference {
+    /**
+        Scales the reference with the fan laws, sound power grows with the 5th
+        power of the speed, which is 50 dB per decade of RPM. Only meant for
+        comparing fan curves on the same card, a stopped fan gives None.
+    **/
+    pub fn estimate(&self, rpm: u32) -> Option<f32> {
+        if rpm == 0 || self.rpm == 0 {
+            return None;
+        }
+
+        Some(self.dba + 50f32 * (rpm as f32 / self.rpm as f32).log10())
+    }
 }
 
 pub enum TemperatureSensor {
@@ -27,23 +69,274 @@ pub enum TemperatureSensor {
     Memory
 }
 
+/// Every temperature sensor read at once, absent sensors are None
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize)]
+pub struct TemperatureReadings {
+    pub edge: Option<f32>,
+    pub junction: Option<f32>,
+    pub memory: Option<f32>
+}
+
+impl TemperatureReadings {
+    /**
+        Blends edge and junction, junction_weight of 0 is the edge alone and 1
+        the junction alone. When one of them is missing the other is used, but
+        the junction only stands in for the edge when it's given any weight.
+    **/
+    pub fn control_temperature(&self, junction_weight: f32) -> Option<f32> {
+        match (self.edge, self.junction) {
+            (Some(edge), Some(junction)) => Some(edge + (junction - edge) * junction_weight),
+            (Some(edge), None) => Some(edge),
+            (None, Some(junction)) if junction_weight > 0f32 => Some(junction),
+            _ => None
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Part {
     Core,
     Memory
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum PcieLevel {
     Gen1,
     Gen3
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpuInitError {
+    HwmonUnavailable(std::io::ErrorKind),
+    NoAmdGpuHwmon
+}
+
+impl std::fmt::Display for GpuInitError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GpuInitError::HwmonUnavailable(kind) => write!(f, "Could not access gpu hwmon: {:?}", kind),
+            GpuInitError::NoAmdGpuHwmon => write!(f, "No hwmon named \"{}\" found under the card", HWMON_NAME)
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PowerLimitError {
+    OutOfRange(RangeInclusive<f32>),
+    /// Holds the limit read back, if it could be read
+    NotApplied(Option<f32>)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OverclockError {
     Disabled,
     Unknown(std::io::ErrorKind),
-    RangesAreImmutable
+    RangesAreImmutable,
+    NotApplied,
+    /// The table is there but in a format the parser doesn't understand
+    ParseFailed,
+    /// The table still changed from read to read when the wait ran out
+    Unsettled,
+    /// Only the clocks were applied, the card rejected some of the voltages
+    VoltageNotApplied
+}
+
+const HWMON_NAME: &str = "amdgpu";
+
+/// Shared by every output showing a clock so the units match
+pub fn format_clock(clock: Option<u32>) -> String {
+    clock.map_or("?".to_string(), |clock| format!("{} MHz", clock))
+}
+
+/// Relative to the device, driver links to the amdgpu driver which links to /sys/module/amdgpu
+const PP_FEATURE_MASK_FILE: &str = "driver/module/parameters/ppfeaturemask";
+/// PP_OVERDRIVE_MASK, pp_od_clk_voltage only accepts writes with it set
+const PP_OVERDRIVE_MASK: u32 = 0x4000;
+
+/// Wait between reads of the table while it's repopulated after a reset
+const PSTATE_RESET_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Wait before a restored power limit which didn't stick is written again
+const RESTORE_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// What the card and driver allow, read without writing anything
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub overdrive: Option<bool>,
+    pub pstate_table: bool,
+    pub junction_sensor: bool,
+    pub memory_sensor: bool,
+    pub fan_control: bool,
+    pub fan_rpm: bool,
+    pub instant_power: bool
+}
+
+impl std::fmt::Display for Capabilities {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+
+        writeln!(f, "Overdrive:       {}", self.overdrive.map_or("unknown", yes_no))?;
+        writeln!(f, "Pstate table:    {}", yes_no(self.pstate_table))?;
+        writeln!(f, "Junction sensor: {}", yes_no(self.junction_sensor))?;
+        writeln!(f, "Memory sensor:   {}", yes_no(self.memory_sensor))?;
+        writeln!(f, "Fan control:     {}", yes_no(self.fan_control))?;
+        writeln!(f, "Fan RPM:         {}", yes_no(self.fan_rpm))?;
+        write!(f, "Instant power:   {}", yes_no(self.instant_power))
+    }
+
+}
+
+/// Directory a node lives in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeDir {
+    Device,
+    Hwmon
+}
+
+/// When the daemon can't run without a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeNeed {
+    Always,
+    /// Only written, so --read-only runs without it
+    Writes,
+    Optional
+}
+
+/**
+    Every node the daemon reads or writes, with what stops working without it.
+    The control loop can't run without the Always nodes, nor without the
+    Writes nodes unless it's read-only.
+**/
+static NODES: &[(NodeDir, &str, NodeNeed, &str)] = &[
+    (NodeDir::Hwmon, "temp1_input", NodeNeed::Always, "temperature"),
+    (NodeDir::Device, "gpu_busy_percent", NodeNeed::Always, "usage"),
+    (NodeDir::Device, "pp_od_clk_voltage", NodeNeed::Writes, "pstate table"),
+    (NodeDir::Device, "pp_dpm_sclk", NodeNeed::Always, "core pstates"),
+    (NodeDir::Device, "pp_dpm_mclk", NodeNeed::Always, "memory pstates"),
+    (NodeDir::Device, "power_dpm_force_performance_level", NodeNeed::Writes, "performance levels"),
+    (NodeDir::Hwmon, "power1_cap", NodeNeed::Writes, "power limits"),
+    (NodeDir::Hwmon, "power1_cap_min", NodeNeed::Always, "power limit range"),
+    (NodeDir::Hwmon, "power1_cap_max", NodeNeed::Always, "power limit range"),
+    (NodeDir::Hwmon, "power1_average", NodeNeed::Optional, "power usage, Performance is entered on usage only"),
+    (NodeDir::Hwmon, "power1_input", NodeNeed::Optional, "instant_power_entry"),
+    (NodeDir::Hwmon, "temp2_input", NodeNeed::Optional, "junction temperature"),
+    (NodeDir::Hwmon, "temp3_input", NodeNeed::Optional, "memory temperature"),
+    (NodeDir::Hwmon, "temp1_crit", NodeNeed::Optional, "crit relative values"),
+    (NodeDir::Hwmon, "fan1_input", NodeNeed::Optional, "fan RPM and stall detection"),
+    (NodeDir::Hwmon, "in0_input", NodeNeed::Optional, "measured core voltage"),
+    (NodeDir::Device, "pp_power_profile_mode", NodeNeed::Optional, "power profiles")
+];
+
+/// Nodes missing on the card, see PolarisGpu::validate_nodes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeValidationReport {
+    /// Along with the feature which needs it, reported by the Nodes preflight check
+    pub missing_required: Vec<(PathBuf, &'static str)>,
+    /// Along with the feature disabled without it
+    pub missing_optional: Vec<(PathBuf, &'static str)>,
+    /// Set when amdgpu.ppfeaturemask has the overdrive bit cleared
+    pub overdrive_disabled: bool
+}
+
+impl std::fmt::Display for NodeValidationReport {
+
+    /// Only the warnings, the missing required nodes are preflight errors
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (path, feature) in self.missing_optional.iter() {
+            writeln!(f, "  No {:?}, disabling {}", path, feature)?;
+        }
+        if self.overdrive_disabled {
+            writeln!(f, "! Overdrive is disabled in amdgpu.ppfeaturemask, the pstate table can't be changed. \
+                Add amdgpu.ppfeaturemask=0xffffffff to the kernel command line to enable it")?;
+        }
+        Ok(())
+    }
+
+}
+
+/// Polaris device IDs, variants sharing a die are told apart by revision only
+static DEVICE_NAMES: &[(&str, &str)] = &[
+    ("67DF", "Radeon RX 470/480/570/580/590"),
+    ("67EF", "Radeon RX 460/560"),
+    ("67FF", "Radeon RX 550/560"),
+    ("699F", "Radeon RX 550")
+];
+
+static SUBSYSTEM_VENDOR_NAMES: &[(&str, &str)] = &[
+    ("1002", "AMD"),
+    ("1043", "ASUS"),
+    ("1458", "Gigabyte"),
+    ("1462", "MSI"),
+    ("148C", "PowerColor"),
+    ("1682", "XFX"),
+    ("1DA2", "Sapphire")
+];
+
+/// Hwmon directories of the card in a stable order
+fn hwmon_candidates(card_dir: &Path) -> Result<Vec<PathBuf>, GpuInitError> {
+    let mut candidates: Vec<PathBuf> = fs::read_dir(card_dir.join("hwmon"))
+        .map_err(|err| GpuInitError::HwmonUnavailable(err.kind()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    candidates.sort();
+
+    Ok(candidates)
+}
+
+/**
+    Returns the device directory of the card* sharing the render node's PCI
+    device, render nodes are numbered from 128 independently of the cards.
+    Links such as the ones in /dev/dri/by-path are followed to the node first.
+**/
+pub fn card_dir_for_render_node(sysfs_root: &Path, render_node: &Path) -> Option<PathBuf> {
+    let render_node = fs::canonicalize(render_node).unwrap_or_else(|_| render_node.to_path_buf());
+    let drm_dir = sysfs_root.join("class/drm");
+    let device = fs::canonicalize(drm_dir.join(render_node.file_name()?).join("device")).ok()?;
+
+    let mut cards: Vec<PathBuf> = fs::read_dir(&drm_dir).ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        // Connectors such as card0-DP-1 are listed next to the cards
+        .filter(|path| path.file_name().and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("card") && !name.contains('-')))
+        .collect();
+    cards.sort();
+
+    cards.into_iter()
+        .map(|card| card.join("device"))
+        .find(|dir| fs::canonicalize(dir).is_ok_and(|dir| dir == device))
+}
+
+/**
+    Returns the hwmon directory of the card whose `name` file matches.
+    The hwmonN numbering can change across reboots, the name doesn't.
+**/
+pub fn find_hwmon_by_name(card_dir: &Path, name: &str) -> Option<PathBuf> {
+    hwmon_candidates(card_dir).ok()?
+        .into_iter()
+        .find(|path| sysfs::try_read_string_from_file(&path.join("name"))
+            .is_some_and(|hwmon_name| hwmon_name.trim() == name))
+}
+
+/**
+    Returns the hwmon directory of the card whose `name` file reads `amdgpu`.
+    The first enumerated hwmon is not necessarily the gpu one, so every
+    sibling is checked in order.
+**/
+fn find_amdgpu_hwmon(card_dir: &Path) -> Result<PathBuf, GpuInitError> {
+    for path in hwmon_candidates(card_dir)? {
+        match sysfs::try_read_string_from_file(&path.join("name")) {
+            Some(name) if name.trim() == HWMON_NAME => return Ok(path),
+            Some(name) => println!("Skipping hwmon {:?} ({})", path, name.trim()),
+            None => println!("Skipping hwmon {:?} (no name)", path)
+        }
+    }
+
+    Err(GpuInitError::NoAmdGpuHwmon)
 }
 
 impl<'a> SysfsDevice for PolarisGpu<'a> {
@@ -55,44 +348,186 @@ impl<'a> AmdGpuSysfsPerformanceLevel for PolarisGpu<'a> {
 }
 
 impl<'a> PolarisGpu<'a> {
-    pub fn new<P: AsRef<Path>>(name: &'a str, sysfs_dir: P) -> Self {
-        let dir = sysfs_dir.as_ref();
-        let hwmon_dir = fs::read_dir(dir.join("hwmon"))
-            .expect("Could not access gpu hwmon!")
-            .next()
-            .expect("Could not enumerate hwmon directory")
-            .expect("Gpu has no fans!")
-            .path();
+    pub fn new<P: AsRef<Path>>(name: &'a str, sysfs_dir: P, hwmon_name: Option<&str>) -> Self {
+        match Self::try_new(name, sysfs_dir, hwmon_name) {
+            Ok(gpu) => gpu,
+            Err(err) => panic!("{}", err)
+        }
+    }
 
+    /// Binds to the hwmon with the given name when there is one, otherwise to the amdgpu one
+    pub fn try_new<P: AsRef<Path>>(name: &'a str, sysfs_dir: P, hwmon_name: Option<&str>) -> Result<Self, GpuInitError> {
+        let dir = sysfs_dir.as_ref();
+        let hwmon_dir = match hwmon_name.map(|hwmon_name| (hwmon_name, find_hwmon_by_name(dir, hwmon_name))) {
+            Some((_, Some(path))) => path,
+            Some((hwmon_name, None)) => {
+                println!("! No hwmon named \"{}\", falling back to \"{}\"", hwmon_name, HWMON_NAME);
+                find_amdgpu_hwmon(dir)?
+            },
+            None => find_amdgpu_hwmon(dir)?
+        };
 
-        PolarisGpu {
+        Ok(PolarisGpu {
             name,
             sysfs_dir: dir.to_path_buf(),
             hwmon_dir: hwmon_dir.clone(),
-            fan: PolarisGpuFan::new(hwmon_dir, 1)
+            temperature_offsets: TemperatureOffsets::default(),
+            noise_reference: None,
+            fan: PolarisGpuFan::discover(hwmon_dir),
+            reader: None
+        })
+    }
+
+    /**
+        Whether the overdrive bit is set in amdgpu.ppfeaturemask, None when
+        the parameter can't be read. Without it pstate writes are rejected.
+    **/
+    pub fn od_feature_enabled(&self) -> Option<bool> {
+        sysfs::try_read_string_from_file(&self.sysfs_dir.join(PP_FEATURE_MASK_FILE))
+            .and_then(|data| Self::parse_feature_mask(&data))
+            .map(|mask| mask & PP_OVERDRIVE_MASK != 0)
+    }
+
+    fn parse_feature_mask(data: &str) -> Option<u32> {
+        let data = data.trim();
+
+        match data.strip_prefix("0x").or_else(|| data.strip_prefix("0X")) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => data.parse::<u32>().ok()
+        }
+    }
+
+    /**
+        Checks every node the daemon uses at once, instead of failing on first use.
+        Nodes which are only written aren't required when read_only.
+    **/
+    pub fn validate_nodes(&self, read_only: bool) -> NodeValidationReport {
+        let mut report = NodeValidationReport {
+            overdrive_disabled: self.od_feature_enabled() == Some(false),
+            ..NodeValidationReport::default()
+        };
+
+        for (dir, node, need, feature) in NODES.iter() {
+            let path = match dir {
+                NodeDir::Device => self.sysfs_dir.join(node),
+                NodeDir::Hwmon => self.hwmon_dir.join(node)
+            };
+
+            if path.is_file() {
+                continue;
+            }
+            // The driver leaves the table out when overdrive is disabled
+            let feature = if *node == Self::PSTATE_TABLE_FILE && report.overdrive_disabled {
+                "pstate table, overdrive is disabled in amdgpu.ppfeaturemask"
+            } else {
+                feature
+            };
+            match need {
+                NodeNeed::Always => report.missing_required.push((path, feature)),
+                NodeNeed::Writes if !read_only => report.missing_required.push((path, feature)),
+                _ => report.missing_optional.push((path, feature))
+            }
+        }
+        report
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            overdrive: self.od_feature_enabled(),
+            pstate_table: self.read_pstates().is_some(),
+            junction_sensor: self.has_sensor(TemperatureSensor::Junction),
+            memory_sensor: self.has_sensor(TemperatureSensor::Memory),
+            fan_control: self.fan.is_some(),
+            fan_rpm: self.fan.as_ref().and_then(|fan| fan.rpm()).is_some(),
+            instant_power: self.power_instant().is_some()
+        }
+    }
+
+    /**
+        Reads the PCI IDs from the device uevent and maps them to a model name,
+        prefixed with the board vendor when it's known. Unknown devices are
+        named by their IDs.
+    **/
+    pub fn detect_name_at<P: AsRef<Path>>(sysfs_dir: P) -> Option<String> {
+        sysfs::try_read_string_from_file(&sysfs_dir.as_ref().join("uevent"))
+            .and_then(|data| Self::parse_name(&data))
+    }
+
+    fn parse_name(uevent: &str) -> Option<String> {
+        let value = |key: &str| uevent.lines()
+            .find_map(|line| line.strip_prefix(key))
+            .map(|value| value.trim().to_uppercase());
+
+        let pci_id = value("PCI_ID=")?;
+        let device_id = pci_id.split(':').nth(1)?;
+        let model = match DEVICE_NAMES.iter().find(|(id, _)| *id == device_id) {
+            Some((_, model)) => model.to_string(),
+            None => format!("AMD GPU {}", pci_id)
+        };
+
+        let vendor = value("PCI_SUBSYS_ID=")
+            .and_then(|subsystem| subsystem.split(':').next().map(str::to_string))
+            .and_then(|vendor_id| SUBSYSTEM_VENDOR_NAMES.iter().find(|(id, _)| *id == vendor_id))
+            .map(|(_, vendor)| *vendor);
+
+        match vendor {
+            Some(vendor) => Some(format!("{} {}", vendor, model)),
+            None => Some(model)
         }
     }
 
     pub fn usage(&self) -> ClampedPercentage {
-        let percent: u32 = sysfs::parse_string_from_file(&self.sysfs_dir.join("gpu_busy_percent"));
-        ClampedPercentage::new(percent)
+        self.try_usage().expect("Failed to read gpu usage")
+    }
+
+    /// Reads usage, temperatures and power through files kept open from now on
+    pub fn keep_files_open(&mut self) {
+        self.reader = Some(sysfs::SysfsReader::default());
+    }
+
+    fn read_value<T: std::str::FromStr>(&self, path: &Path) -> Option<T> {
+        match &self.reader {
+            Some(reader) => reader.try_parse(path),
+            None => sysfs::try_parse_string_from_file(&path)
+        }
+    }
+
+    pub fn try_usage(&self) -> Option<ClampedPercentage> {
+        self.read_value::<u32>(&self.sysfs_dir.join("gpu_busy_percent"))
+            .and_then(|percent| ClampedPercentage::try_new(percent).ok())
     }
     
-    pub fn fan(&self) -> &PolarisGpuFan {
-        &self.fan
+    pub fn fan(&self) -> Option<&PolarisGpuFan> {
+        self.fan.as_ref()
     }
 
     pub fn temperature(&self) -> f32 {
         self.read_sensor(TemperatureSensor::Edge).expect("GPU has no temperature sensor!")
     }
 
+    /// Edge temperature (C) the driver considers critical, uncalibrated
+    pub fn critical_temperature(&self) -> Option<f32> {
+        sysfs::try_parse_string_from_file::<f32, _>(&self.hwmon_path("temp1_crit"))
+            .map(|temperature| temperature / 1000f32)
+    }
+
     fn hwmon_path(&self, property: &'static str) -> PathBuf {
         self.hwmon_dir.join(property)
     }
 
     pub fn power_usage(&self) -> f32 {
-        let wattage: f32 = sysfs::parse_string_from_file(&self.hwmon_path("power1_average"));
-        wattage / Self::WATTAGE_DIVISOR
+        self.try_power_usage().expect("Failed to read gpu power usage")
+    }
+
+    pub fn try_power_usage(&self) -> Option<f32> {
+        self.read_value::<f32>(&self.hwmon_path("power1_average"))
+            .map(|wattage| wattage / Self::WATTAGE_DIVISOR)
+    }
+
+    /// Instantaneous power usage, reacts faster than power_usage but is noisier
+    pub fn power_instant(&self) -> Option<f32> {
+        self.read_value::<f32>(&self.hwmon_path("power1_input"))
+            .map(|wattage| wattage / Self::WATTAGE_DIVISOR)
     }
 
     pub fn power_limit(&self) -> f32 {
@@ -100,9 +535,32 @@ impl<'a> PolarisGpu<'a> {
         wattage / Self::WATTAGE_DIVISOR
     }
 
+    pub fn try_power_limit(&self) -> Option<f32> {
+        sysfs::try_parse_string_from_file::<f32, _>(&self.hwmon_path("power1_cap"))
+            .map(|wattage| wattage / Self::WATTAGE_DIVISOR)
+    }
+
     const WATTAGE_DIVISOR: f32 = 1000000f32;
+    /// The driver rounds the cap to whole watts
+    const POWER_LIMIT_TOLERANCE: f32 = 1f32;
     fn to_real_wattage(value: f32) -> u32 { (value * Self::WATTAGE_DIVISOR) as u32 }
 
+    pub fn try_power_limit_range(&self) -> Option<RangeInclusive<f32>> {
+        let min = sysfs::try_parse_string_from_file::<f32, _>(&self.hwmon_path("power1_cap_min"))?;
+        let max = sysfs::try_parse_string_from_file::<f32, _>(&self.hwmon_path("power1_cap_max"))?;
+
+        Some(RangeInclusive::new(min / Self::WATTAGE_DIVISOR, max / Self::WATTAGE_DIVISOR))
+    }
+
+    /// Files the control loop writes to, other than the fan's
+    pub fn control_files(&self) -> Vec<PathBuf> {
+        vec![
+            self.sysfs_dir.join(Self::PSTATE_TABLE_FILE),
+            self.sysfs_dir.join(self.performance_level_file()),
+            self.hwmon_path("power1_cap")
+        ]
+    }
+
     pub fn power_limit_range(&self) -> RangeInclusive<f32> {
         let min: f32 = sysfs::parse_string_from_file(&self.hwmon_path("power1_cap_min"));
         let max: f32 = sysfs::parse_string_from_file(&self.hwmon_path("power1_cap_max"));
@@ -112,7 +570,7 @@ impl<'a> PolarisGpu<'a> {
         RangeInclusive::new(min / divisor, max / divisor)
     }
 
-    pub fn set_power_limit(&self, wattage: f32) -> () {
+    pub fn set_power_limit(&self, wattage: f32) {
         let path: PathBuf = self.hwmon_path("power1_cap");
         let range: RangeInclusive<f32> = self.power_limit_range();
 
@@ -124,6 +582,35 @@ impl<'a> PolarisGpu<'a> {
         }
     }
 
+    /**
+        Writes the power limit and reads it back. The driver sometimes ignores
+        a write issued right after a performance level change, so it's written
+        once more after retry_delay when it didn't stick.
+    **/
+    pub fn set_power_limit_verified(&self, wattage: f32, retry_delay: Duration) -> Result<(), PowerLimitError> {
+        let path: PathBuf = self.hwmon_path("power1_cap");
+        let range: RangeInclusive<f32> = self.power_limit_range();
+
+        if !range.contains(&wattage) {
+            return Err(PowerLimitError::OutOfRange(range));
+        }
+
+        let real_value: u32 = Self::to_real_wattage(wattage);
+        for retry in [false, true].iter() {
+            if *retry {
+                thread::sleep(retry_delay);
+            }
+
+            let applied = sysfs::try_write(&path, &real_value.to_string()).is_ok() &&
+                self.try_power_limit().is_some_and(|limit| (limit - wattage).abs() <= Self::POWER_LIMIT_TOLERANCE);
+            if applied {
+                return Ok(());
+            }
+        }
+
+        Err(PowerLimitError::NotApplied(self.try_power_limit()))
+    }
+
     const PSTATE_MEMORY_FILE: &'static str = "pp_dpm_mclk";
     const PSTATE_CORE_FILE: &'static str = "pp_dpm_sclk";
 
@@ -151,13 +638,99 @@ impl<'a> PolarisGpu<'a> {
         sysfs::write(path, &state.to_string());
     }
 
+    /// Clock (MHz) of the pstate the part is currently running at
+    pub fn current_clock(&self, part: Part) -> Option<u32> {
+        self.read_dpm_states(part).and_then(|data| Self::parse_current_clock(&data))
+    }
+
+    /// Measured core voltage (mV), differs from the table under load by design
+    pub fn core_voltage(&self) -> Option<u32> {
+        sysfs::try_parse_string_from_file::<u32, _>(&self.hwmon_path("in0_input"))
+    }
+
+    const MEMORY_ERROR_COUNT_FILE: &'static str = "ras/umc_err_count";
+
+    /**
+        Correctable and uncorrectable memory controller errors counted by the
+        driver since boot, None on cards without RAS support like most Polaris.
+    **/
+    pub fn memory_errors(&self) -> Option<(u64, u64)> {
+        sysfs::try_read_string_from_file(&self.sysfs_dir.join(Self::MEMORY_ERROR_COUNT_FILE))
+            .and_then(|data| Self::parse_error_count(&data))
+    }
+
+    /// Parses the "ue: N" and "ce: N" lines of a RAS error count
+    fn parse_error_count(data: &str) -> Option<(u64, u64)> {
+        let count = |key: &str| data.lines()
+            .find_map(|line| line.strip_prefix(key))
+            .and_then(|count| count.trim().parse::<u64>().ok());
+
+        Some((count("ce:")?, count("ue:")?))
+    }
+
+    /// Table voltage (mV) of the core pstate currently in use
+    pub fn current_table_voltage(&self) -> Option<u32> {
+        let index = self.read_dpm_states(Part::Core).and_then(|data| Self::parse_current_index(&data))?;
+        self.read_pstates()?.get_state(Part::Core, index).map(|state| state.voltage)
+    }
+
+    /// Whether the part runs at its lowest pstate, None when unreadable
+    pub fn at_lowest_state(&self, part: Part) -> Option<bool> {
+        self.read_dpm_states(part).and_then(|data| Self::parse_current_index(&data)).map(|index| index == 0)
+    }
+
+    fn read_dpm_states(&self, part: Part) -> Option<String> {
+        let file = match part {
+            Part::Core => Self::PSTATE_CORE_FILE,
+            Part::Memory => Self::PSTATE_MEMORY_FILE
+        };
+
+        sysfs::try_read_string_from_file(&self.sysfs_dir.join(file))
+    }
+
+    /**
+        Average power usage, None when unreadable or when it reads 0W above the lowest
+        core state. Some cards report 0W in states they can't measure, which isn't idle.
+    **/
+    pub fn measured_power_usage(&self) -> Option<f32> {
+        match self.try_power_usage() {
+            Some(power) if power == 0f32 && self.at_lowest_state(Part::Core) == Some(false) => None,
+            power => power
+        }
+    }
+
     pub fn read_pstates(&self) -> Option<PolarisGpuTable> {
-         sysfs::try_read_string_from_file(&self.sysfs_dir.join(Self::PSTATE_TABLE_FILE))
-             .map_or(None, |data| PolarisGpuTable::try_parse(&data))
+        self.try_read_pstates().ok()
+    }
+
+    /// Tells a missing or empty table (overdrive disabled) apart from one that couldn't be parsed
+    pub fn try_read_pstates(&self) -> Result<PolarisGpuTable, OverclockError> {
+        match self.raw_pstates() {
+            Some(data) if !data.trim().is_empty() =>
+                PolarisGpuTable::try_parse(&data).ok_or(OverclockError::ParseFailed),
+            _ => Err(OverclockError::Disabled)
+        }
+    }
+
+    /// Unparsed content of the table file, for bug reports
+    pub fn raw_pstates(&self) -> Option<String> {
+        sysfs::try_read_string_from_file(&self.sysfs_dir.join(Self::PSTATE_TABLE_FILE))
     }
 
     const PSTATE_TABLE_FILE: &'static str = "pp_od_clk_voltage";
 
+    /// Clock range of the part, read without requiring the rest of the table to parse
+    pub fn clock_range(&self, part: Part) -> Option<RangeInclusive<u32>> {
+        sysfs::try_read_string_from_file(&self.sysfs_dir.join(Self::PSTATE_TABLE_FILE))
+            .and_then(|data| PolarisGpuTable::try_parse_range(&data, Some(part)))
+    }
+
+    /// Voltage range, read without requiring the rest of the table to parse
+    pub fn voltage_range(&self) -> Option<RangeInclusive<u32>> {
+        sysfs::try_read_string_from_file(&self.sysfs_dir.join(Self::PSTATE_TABLE_FILE))
+            .and_then(|data| PolarisGpuTable::try_parse_range(&data, None))
+    }
+
     fn table_to_commands(table: &PolarisGpuTable) -> Vec::<String> {
         let mut commands = Vec::new();
         for part in [Part::Core, Part::Memory].iter() {
@@ -175,33 +748,72 @@ impl<'a> PolarisGpu<'a> {
         commands
     }
 
+    /**
+        Commands changing the current table into the new one, each paired with a
+        fallback which sets the same clock but keeps the current voltage.
+    **/
+    fn pstate_commands(current_table: &PolarisGpuTable, new_table: &PolarisGpuTable) -> Vec<(String, String)> {
+        let current_table_cmds = Self::table_to_commands(current_table);
+        let mut clock_only_table = new_table.clone();
+        clock_only_table.keep_voltages(current_table);
+
+        Self::table_to_commands(new_table).into_iter()
+            .zip(Self::table_to_commands(&clock_only_table))
+            .filter(|(cmd, _)| !current_table_cmds.contains(cmd))
+            .collect()
+    }
+
+    /**
+        Writes the states which differ. Voltage locked cards reject a state with
+        a new voltage, its clock is still applied with the current voltage then
+        and VoltageNotApplied returned once the rest is written.
+    **/
     pub fn set_pstates(&self, new_table: &PolarisGpuTable) -> Result<(), OverclockError> {
-        match self.read_pstates() {
-            Some(current_table) => {
+        match self.try_read_pstates() {
+            Ok(current_table) => {
                 if current_table.voltage_range().eq(new_table.voltage_range()) &&
                     current_table.clock_range(Part::Core).eq(new_table.clock_range(Part::Core)) &&
                     current_table.clock_range(Part::Memory).eq(new_table.clock_range(Part::Memory))
                 {
-                    let current_table_cmds = Self::table_to_commands(&current_table);
-                    let mut new_table_cmds = Self::table_to_commands(&new_table);
-                    new_table_cmds.retain(|element| !current_table_cmds.contains(element));
+                    let new_table_cmds = Self::pstate_commands(&current_table, new_table);
 
                     let path = self.sysfs_dir.join(Self::PSTATE_TABLE_FILE);
 
+                    if new_table_cmds.is_empty() {
+                        return Ok(());
+                    }
+
+                    let mut batch: Vec<&str> = new_table_cmds.iter().map(|(cmd, _)| cmd.as_str()).collect();
+                    batch.push("c");
+
+                    match sysfs::write_lines(&path, &batch) {
+                        Ok(_) => return Ok(()),
+                        Err(err) => println!("Batched write to {:?} failed ({}), writing line by line", path, err)
+                    }
+
                     let mut revert = false;
-                    for cmd in new_table_cmds.iter() {
-                        if sysfs::try_write(&path, &cmd).is_err() {
-                            println!("Writing {:?} to {} failed", path, cmd);
-                            revert = true;
-                            break;
+                    let mut voltage_skipped = false;
+                    for (cmd, clock_only_cmd) in new_table_cmds.iter() {
+                        if sysfs::try_write(&path, cmd).is_ok() {
+                            continue;
                         }
+                        if clock_only_cmd != cmd && sysfs::try_write(&path, clock_only_cmd).is_ok() {
+                            println!("Writing {:?} to {} failed, applied {} keeping the voltage", path, cmd, clock_only_cmd);
+                            voltage_skipped = true;
+                            continue;
+                        }
+                        println!("Writing {:?} to {} failed", path, cmd);
+                        revert = true;
+                        break;
                     };
 
                     if !revert {
-                        if new_table_cmds.len() > 0 {
-                            sysfs::write(path, "c");
+                        sysfs::write(path, "c");
+                        if voltage_skipped {
+                            Err(OverclockError::VoltageNotApplied)
+                        } else {
+                            Ok(())
                         }
-                        Ok(())
                     } else {
                         self.reset_pstates();
                         Err(OverclockError::Disabled)
@@ -210,13 +822,138 @@ impl<'a> PolarisGpu<'a> {
                     Err(OverclockError::RangesAreImmutable)
                 }
             },
-            None => Err(OverclockError::Disabled)
+            Err(err) => Err(err)
+        }
+    }
+
+    /**
+        Probes voltage control by writing the current lowest core state back.
+        Some cards reject any per-state voltage write even with OD enabled.
+    **/
+    pub fn supports_voltage_control(&self) -> bool {
+        let path = self.sysfs_dir.join(Self::PSTATE_TABLE_FILE);
+
+        match self.read_pstates().and_then(|table| table.get_state(Part::Core, 0)) {
+            Some(state) => sysfs::try_write(path, &format!("s 0 {} {}", state.clock, state.voltage)).is_ok(),
+            None => false
+        }
+    }
+
+    /// Whether the live table is exactly the given one
+    pub fn pstates_match(&self, table: &PolarisGpuTable) -> bool {
+        self.read_pstates().is_some_and(|current| current.eq(table))
+    }
+
+    /// Applies the table and reads it back to make sure the driver took it
+    pub fn set_pstates_verified(&self, table: &PolarisGpuTable) -> Result<(), OverclockError> {
+        self.set_pstates(table)?;
+
+        if self.pstates_match(table) {
+            Ok(())
+        } else {
+            Err(OverclockError::NotApplied)
+        }
+    }
+
+    /**
+        Quantizes the table to the steps the driver accepts, writes it, retrying once,
+        and reads it back. The previous table is restored when it didn't stick.
+    **/
+    pub fn apply_table_safely(&self, table: &PolarisGpuTable, clock_step: u32, voltage_step: u32) -> Result<(), OverclockError> {
+        let previous = self.try_read_pstates()?;
+        let mut table = table.clone();
+        table.quantize(clock_step, voltage_step);
+
+        let mut written = self.set_pstates(&table);
+        if let Some(err) = written.as_ref().err().filter(|err| **err != OverclockError::VoltageNotApplied) {
+            println!("! Writing the pstate table failed ({:?}), retrying once", err);
+            written = self.set_pstates(&table);
+        }
+
+        // Only the clocks can be checked when the card kept some voltages
+        let voltage_skipped = match written {
+            Ok(()) => false,
+            Err(OverclockError::VoltageNotApplied) => {
+                table.keep_voltages(&self.try_read_pstates()?);
+                true
+            },
+            Err(err) => return Err(err)
+        };
+
+        if self.pstates_match(&table) {
+            if voltage_skipped { Err(OverclockError::VoltageNotApplied) } else { Ok(()) }
+        } else {
+            println!("! The pstate table didn't stick, restoring the previous one");
+            if let Err(err) = self.set_pstates(&previous) {
+                println!("! Restoring the previous pstate table failed: {:?}", err);
+            }
+            Err(OverclockError::NotApplied)
+        }
+    }
+
+    const FIRMWARE_FAN_CURVE_FILE: &'static str = "gpu_od/fan_ctrl/fan_curve";
+    /// Points the firmware fan curve holds
+    const FIRMWARE_FAN_CURVE_POINTS: usize = 5;
+
+    /**
+        Programs the firmware's own fan curve, which takes over whenever manual control
+        lapses, so a daemon killed with SIGKILL doesn't leave the fan stuck at a low speed.
+        Only newer cards expose the curve, returns false without writing when it's missing.
+    **/
+    pub fn set_failsafe_firmware_curve(&self, points: &[CurvePoint]) -> bool {
+        let path = self.sysfs_dir.join(Self::FIRMWARE_FAN_CURVE_FILE);
+        if !path.is_file() {
+            return false;
+        }
+
+        let mut lines: Vec<String> = points.iter()
+            .take(Self::FIRMWARE_FAN_CURVE_POINTS)
+            .enumerate()
+            .map(|(index, point)| format!("{} {} {}", index, point.temperature, point.fan_speed.0.round()))
+            .collect();
+        lines.push("c".to_string());
+
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        match sysfs::write_lines(&path, &lines) {
+            Ok(_) => true,
+            Err(err) => {
+                println!("! Failed to program the firmware fan curve: {}", err);
+                false
+            }
         }
     }
 
     pub fn reset_pstates(&self) {
+        self.try_reset_pstates().expect("Failed to reset the pstate table");
+    }
+
+    pub fn try_reset_pstates(&self) -> Result<(), std::io::Error> {
         let path: PathBuf = self.sysfs_dir.join(Self::PSTATE_TABLE_FILE);
-        sysfs::write(path, "r");
+        sysfs::try_write(path, "r")
+    }
+
+    /**
+        Resets the table and returns it once two reads in a row agree, the
+        driver repopulates it a moment after the reset so a read right away
+        can still see the old or a partial table.
+    **/
+    pub fn reset_pstates_and_wait(&self, timeout: Duration) -> Result<PolarisGpuTable, OverclockError> {
+        let path: PathBuf = self.sysfs_dir.join(Self::PSTATE_TABLE_FILE);
+        sysfs::try_write(path, "r").map_err(|err| OverclockError::Unknown(err.kind()))?;
+
+        let deadline = Instant::now() + timeout;
+        let mut previous: Option<PolarisGpuTable> = None;
+        loop {
+            thread::sleep(PSTATE_RESET_POLL_INTERVAL);
+
+            // A partial table may not parse yet
+            let table = self.try_read_pstates().ok();
+            match (table, &previous) {
+                (Some(table), Some(previous)) if table == *previous => return Ok(table),
+                _ if Instant::now() >= deadline => return Err(OverclockError::Unsettled),
+                (table, _) => previous = table
+            }
+        }
     }
 
 
@@ -231,8 +968,8 @@ impl<'a> PolarisGpu<'a> {
     fn parse_current_pstate(data: String) -> u32 {
         let current_state: &str = data
             .split("\n")
-            .filter(|line| line.contains('*'))
-            .next().expect("No current memory pstate?!")
+            .find(|line| line.contains('*'))
+            .expect("No current memory pstate?!")
             .trim()
             .split(":")
             .next().expect("State is not numbered?!");
@@ -240,34 +977,169 @@ impl<'a> PolarisGpu<'a> {
         current_state.parse::<u32>().expect("State index is not a number")
     }
 
+    /// Same data as parse_current_pstate, returns the clock of the current state instead
+    fn parse_current_clock(data: &str) -> Option<u32> {
+        data.lines()
+            .find(|line| line.contains('*'))?
+            .split_whitespace()
+            .nth(1)?
+            .to_lowercase()
+            .trim_end_matches("mhz")
+            .parse::<u32>().ok()
+    }
+
+    /// Same data as parse_current_pstate, without panicking when no state is marked current
+    fn parse_current_index(data: &str) -> Option<usize> {
+        data.lines()
+            .find(|line| line.contains('*'))?
+            .split(':')
+            .next()?
+            .trim()
+            .parse::<usize>().ok()
+    }
+
     const POWER_PROFILE_MODE_FILE: &'static str = "pp_power_profile_mode";
 
     pub fn set_power_profile_mode(&self, mode: u8) {
+        self.try_set_power_profile_mode(mode).expect("Failed to set the power profile mode");
+    }
+
+    /// The driver rejects the write unless the performance level is manual
+    pub fn try_set_power_profile_mode(&self, mode: u8) -> Result<(), std::io::Error> {
         let path: PathBuf = self.sysfs_dir.join(Self::POWER_PROFILE_MODE_FILE);
-        sysfs::write(path, &mode.to_string());
+        sysfs::try_write(path, &mode.to_string())
+    }
+
+    /// Index of the power profile mode with the given name, as listed by the card
+    pub fn power_profile_index(&self, name: &str) -> Option<u8> {
+        sysfs::try_read_string_from_file(&self.sysfs_dir.join(Self::POWER_PROFILE_MODE_FILE))
+            .and_then(|data| Self::parse_power_profiles(&data).into_iter()
+                .find(|(_, profile)| profile.eq_ignore_ascii_case(name))
+                .map(|(index, _)| index))
+    }
+
+    /// Index of the power profile mode marked as current
+    pub fn current_power_profile(&self) -> Option<u8> {
+        sysfs::try_read_string_from_file(&self.sysfs_dir.join(Self::POWER_PROFILE_MODE_FILE))?
+            .lines()
+            .find(|line| line.contains('*'))?
+            .split_whitespace()
+            .next()?
+            .parse::<u8>().ok()
+    }
+
+    /**
+        Reads everything the daemon controls, see restore_state. The performance
+        level decides whether the pstates and power profile take effect, so it's
+        captured too.
+    **/
+    pub fn capture_state(&self) -> CardState {
+        let has_performance_level = self.sysfs_dir.join(self.performance_level_file()).is_file();
+        let performance_level = if has_performance_level { Some(self.performance_level()) } else { None };
+        // The profile can only be written back at manual, at any other level it wasn't in effect anyway
+        let manual = performance_level.as_ref().is_none_or(|level| *level == PerformanceLevel::Manual);
+
+        CardState {
+            pstate_table: self.raw_pstates(),
+            power_limit: self.try_power_limit(),
+            performance_level,
+            fan_mode: self.fan().map(|fan| if fan.is_firmware_controlled() { FanMode::Auto } else { FanMode::Manual }),
+            fan_speed: self.fan().and_then(|fan| fan.try_speed()),
+            pcie_level: self.try_pcie_level(),
+            power_profile_mode: self.current_power_profile().filter(|_| manual)
+        }
+    }
+
+    /**
+        Writes a captured state back, skipping whatever it doesn't hold. The
+        performance level goes before the power profile, which the driver only
+        takes at manual, and the fan is handed over last.
+    **/
+    pub fn restore_state(&self, state: &CardState) -> Result<(), RestoreError> {
+        if let Some(data) = &state.pstate_table {
+            let table = PolarisGpuTable::try_parse(data).ok_or(RestoreError::UnparsedTable)?;
+            self.set_pstates_verified(&table).map_err(RestoreError::Pstates)?;
+        }
+        if let Some(limit) = state.power_limit {
+            self.set_power_limit_verified(limit, RESTORE_RETRY_DELAY).map_err(RestoreError::PowerLimit)?;
+        }
+        if let Some(level) = &state.performance_level {
+            self.try_set_performance_level(level.clone())
+                .map_err(|err| RestoreError::PerformanceLevel(err.kind()))?;
+        }
+        if let Some(mode) = state.power_profile_mode {
+            self.try_set_power_profile_mode(mode).map_err(|err| RestoreError::PowerProfile(err.kind()))?;
+        }
+        if let Some(level) = state.pcie_level {
+            self.try_set_pcie_level(level).map_err(|err| RestoreError::PcieLevel(err.kind()))?;
+        }
+
+        if let (Some(fan), Some(mode)) = (self.fan(), state.fan_mode) {
+            fan.set_mode(mode);
+            if let (FanMode::Manual, Some(speed)) = (mode, state.fan_speed) {
+                fan.set_speed(speed);
+            }
+        }
+        Ok(())
+    }
+
+    /**
+        Example data:  
+        NUM        MODE_NAME     SCLK_UP_HYST   SCLK_DOWN_HYST ...  
+          0   BOOTUP_DEFAULT:        -              - ...  
+          1 3D_FULL_SCREEN *:        0            100 ...  
+          2     POWER_SAVING:       10              0 ...  
+
+        Returns the index and name of every profile, the current one is marked with *
+    **/
+    fn parse_power_profiles(data: &str) -> Vec<(u8, String)> {
+        data.lines()
+            .filter_map(|line| {
+                let mut columns = line.split_whitespace();
+                let index = columns.next()?.parse::<u8>().ok()?;
+                let name = columns.next()?.trim_end_matches(':').trim_end_matches('*');
+
+                Some((index, name.to_string()))
+            })
+            .collect()
     }
 
     const PCIE_LEVEL_FILE: &'static str = "pp_dpm_pcie";
     // TODO: Read real available levels, maybe split it into bandwidth and width
 
     pub fn pcie_level(&self) -> PcieLevel {
-        let mode: u32 = sysfs::parse_string_from_file(&self.sysfs_dir.join(Self::PCIE_LEVEL_FILE));
+        self.try_pcie_level().expect("Unknown PCIE mode")
+    }
 
-        match mode {
-            0 => PcieLevel::Gen1,
-            1 => PcieLevel::Gen3,
-            _ => panic!("Unknown PCIE mode")
+    /// Cards with a single link level, or none listed, reject level writes
+    pub fn supports_pcie_switching(&self) -> bool {
+        sysfs::try_read_string_from_file(&self.sysfs_dir.join(Self::PCIE_LEVEL_FILE))
+            .is_some_and(|data| data.lines().filter(|line| line.contains(':')).count() >= 2)
+    }
+
+    /// None when the link speed can't be read or isn't one of the two known levels
+    pub fn try_pcie_level(&self) -> Option<PcieLevel> {
+        let data = sysfs::try_read_string_from_file(&self.sysfs_dir.join(Self::PCIE_LEVEL_FILE))?;
+
+        match Self::parse_current_index(&data)? {
+            0 => Some(PcieLevel::Gen1),
+            1 => Some(PcieLevel::Gen3),
+            _ => None
         }
     }
 
     pub fn set_pcie_level(&self, mode: PcieLevel) {
+        self.try_set_pcie_level(mode).expect("Failed to set the PCIe level");
+    }
+
+    pub fn try_set_pcie_level(&self, mode: PcieLevel) -> Result<(), std::io::Error> {
         let value = match mode {
             PcieLevel::Gen1 => 0,
             PcieLevel::Gen3 => 1
         };
         let path = self.sysfs_dir.join(Self::PCIE_LEVEL_FILE);
 
-        sysfs::write(path, &value.to_string());
+        sysfs::try_write(path, &value.to_string())
     }
 
 
@@ -277,14 +1149,58 @@ impl<'a> PolarisGpu<'a> {
         path.is_file()
     }
 
+    pub fn set_temperature_offsets(&mut self, offsets: TemperatureOffsets) {
+        self.temperature_offsets = offsets;
+    }
+
+    pub fn set_noise_reference(&mut self, reference: NoiseReference) {
+        self.noise_reference = Some(reference);
+    }
+
+    /// Rough fan loudness (dBA) at the current RPM, None without a noise reference
+    pub fn estimated_noise(&self) -> Option<f32> {
+        let rpm = self.fan()?.rpm()?;
+
+        self.noise_reference?.estimate(rpm)
+    }
+
+    fn temperature_offset(&self, sensor: &TemperatureSensor) -> f32 {
+        match sensor {
+            TemperatureSensor::Edge => self.temperature_offsets.edge,
+            TemperatureSensor::Junction => self.temperature_offsets.junction,
+            TemperatureSensor::Memory => self.temperature_offsets.memory
+        }
+    }
+
+    /// Reads each tempN_input once, a missing file simply fails to open
+    pub fn all_temperatures(&self) -> TemperatureReadings {
+        let read = |sensor: TemperatureSensor| {
+            let offset = self.temperature_offset(&sensor);
+            self.read_value::<f32>(&self.get_sensor_path(sensor))
+                .map(|value| value / 1000f32 + offset)
+        };
+
+        TemperatureReadings {
+            edge: read(TemperatureSensor::Edge),
+            junction: read(TemperatureSensor::Junction),
+            memory: read(TemperatureSensor::Memory)
+        }
+    }
+
+    /// Calibrated reading, see read_sensor_raw for the value the driver reports
     pub fn read_sensor(&self, sensor: TemperatureSensor) -> std::option::Option<f32> {
+        let offset = self.temperature_offset(&sensor);
+
+        self.read_sensor_raw(sensor).map(|temperature| temperature + offset)
+    }
+
+    pub fn read_sensor_raw(&self, sensor: TemperatureSensor) -> std::option::Option<f32> {
         let path = self.get_sensor_path(sensor);
 
         if !path.is_file() {
             None
         } else {
-            let value: f32 = sysfs::parse_string_from_file(&path);
-            Some(value / 1000f32)
+            self.read_value::<f32>(&path).map(|value| value / 1000f32)
         }
     }
 
@@ -303,3 +1219,133 @@ impl<'a> PolarisGpu<'a> {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_hwmon(card_dir: &Path, hwmon: &str, name: &str) {
+        let dir = card_dir.join("hwmon").join(hwmon);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("name"), format!("{}\n", name)).unwrap();
+    }
+
+    #[test]
+    fn finds_amdgpu_hwmon_among_siblings() {
+        let card_dir = std::env::temp_dir().join(format!("sentinel-hwmon-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&card_dir);
+
+        create_hwmon(&card_dir, "hwmon0", "nct6797");
+        create_hwmon(&card_dir, "hwmon1", "amdgpu");
+
+        assert_eq!(find_amdgpu_hwmon(&card_dir), Ok(card_dir.join("hwmon").join("hwmon1")));
+
+        fs::remove_dir_all(&card_dir).unwrap();
+    }
+
+    #[test]
+    fn reports_missing_amdgpu_hwmon() {
+        let card_dir = std::env::temp_dir().join(format!("sentinel-no-hwmon-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&card_dir);
+
+        create_hwmon(&card_dir, "hwmon0", "k10temp");
+
+        assert_eq!(find_amdgpu_hwmon(&card_dir), Err(GpuInitError::NoAmdGpuHwmon));
+
+        fs::remove_dir_all(&card_dir).unwrap();
+    }
+
+    #[test]
+    fn parses_name_from_uevent() {
+        let uevent = "DRIVER=amdgpu\nPCI_CLASS=30000\nPCI_ID=1002:67DF\nPCI_SUBSYS_ID=1DA2:E366\n";
+
+        assert_eq!(PolarisGpu::parse_name(uevent), Some("Sapphire Radeon RX 470/480/570/580/590".to_string()));
+        assert_eq!(PolarisGpu::parse_name("PCI_ID=1002:6863\n"), Some("AMD GPU 1002:6863".to_string()));
+        assert_eq!(PolarisGpu::parse_name("DRIVER=amdgpu\n"), None);
+    }
+
+    #[test]
+    fn blends_edge_and_junction() {
+        let readings = TemperatureReadings { edge: Some(60f32), junction: Some(80f32), memory: None };
+
+        assert_eq!(readings.control_temperature(0f32), Some(60f32));
+        assert_eq!(readings.control_temperature(0.75f32), Some(75f32));
+        assert_eq!(TemperatureReadings { edge: None, ..readings }.control_temperature(0f32), None);
+        assert_eq!(TemperatureReadings { edge: None, ..readings }.control_temperature(0.5f32), Some(80f32));
+        assert_eq!(TemperatureReadings { junction: None, ..readings }.control_temperature(0.5f32), Some(60f32));
+    }
+
+    #[test]
+    fn pairs_pstate_commands_with_clock_only_fallbacks() {
+        let ranges = "OD_RANGE:\nSCLK: 300MHz 2000MHz\nMCLK: 300MHz 2250MHz\nVDDC: 750mV 1150mV\n";
+        let current = PolarisGpuTable::parse(&format!("OD_SCLK:\n0: 300MHz 750mV\n1: 1250MHz 1025mV\n\
+            OD_MCLK:\n0: 300MHz 800mV\n{}", ranges));
+        let new = PolarisGpuTable::parse(&format!("OD_SCLK:\n0: 300MHz 750mV\n1: 1300MHz 1000mV\n\
+            OD_MCLK:\n0: 400MHz 800mV\n{}", ranges));
+
+        assert_eq!(PolarisGpu::pstate_commands(&current, &new), vec![
+            ("s 1 1300 1000".to_string(), "s 1 1300 1025".to_string()),
+            ("m 0 400 800".to_string(), "m 0 400 800".to_string())
+        ]);
+    }
+
+    #[test]
+    fn estimates_noise_from_reference() {
+        let reference = NoiseReference { rpm: 1000, dba: 30f32 };
+
+        assert_eq!(reference.estimate(1000), Some(30f32));
+        assert_eq!(reference.estimate(2000).map(|dba| (dba * 10f32).round() / 10f32), Some(45.1f32));
+        assert!(reference.estimate(500).unwrap() < 30f32);
+        assert_eq!(reference.estimate(0), None);
+    }
+
+    #[test]
+    fn parses_feature_mask() {
+        assert_eq!(PolarisGpu::parse_feature_mask("0xfffd7fff\n"), Some(0xfffd7fff));
+        assert_eq!(PolarisGpu::parse_feature_mask("4294967295"), Some(0xffffffff));
+        assert_eq!(PolarisGpu::parse_feature_mask("0xfffd7fff").map(|mask| mask & PP_OVERDRIVE_MASK != 0), Some(true));
+        assert_eq!(PolarisGpu::parse_feature_mask("0xfffd3fff").map(|mask| mask & PP_OVERDRIVE_MASK != 0), Some(false));
+    }
+
+    #[test]
+    fn parses_current_clock() {
+        assert_eq!(PolarisGpu::parse_current_clock("0: 300Mhz\n1: 1000Mhz *\n2: 1500Mhz\n"), Some(1000));
+        assert_eq!(PolarisGpu::parse_current_clock("0: 300Mhz\n1: 1000Mhz\n"), None);
+        assert_eq!(format_clock(Some(1244)), "1244 MHz");
+        assert_eq!(PolarisGpu::parse_current_index("0: 300Mhz\n1: 1000Mhz *\n"), Some(1));
+    }
+
+    #[test]
+    fn parses_memory_error_count() {
+        assert_eq!(PolarisGpu::parse_error_count("ue: 1\nce: 12\n"), Some((12, 1)));
+        assert_eq!(PolarisGpu::parse_error_count("ue: 0\n"), None);
+    }
+
+    #[test]
+    fn parses_power_profiles() {
+        let data = "NUM        MODE_NAME     SCLK_UP_HYST   SCLK_DOWN_HYST SCLK_ACTIVE_LEVEL\n\
+          0   BOOTUP_DEFAULT:        -              -              -\n\
+          1 3D_FULL_SCREEN *:        0            100             30\n\
+          2     POWER_SAVING:       10              0             30\n";
+
+        assert_eq!(PolarisGpu::parse_power_profiles(data), vec![
+            (0, "BOOTUP_DEFAULT".to_string()),
+            (1, "3D_FULL_SCREEN".to_string()),
+            (2, "POWER_SAVING".to_string())
+        ]);
+    }
+
+    #[test]
+    fn finds_hwmon_by_name() {
+        let card_dir = std::env::temp_dir().join(format!("sentinel-named-hwmon-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&card_dir);
+
+        create_hwmon(&card_dir, "hwmon3", "amdgpu");
+        create_hwmon(&card_dir, "hwmon4", "nct6797");
+
+        assert_eq!(find_hwmon_by_name(&card_dir, "nct6797"), Some(card_dir.join("hwmon").join("hwmon4")));
+        assert_eq!(find_hwmon_by_name(&card_dir, "k10temp"), None);
+
+        fs::remove_dir_all(&card_dir).unwrap();
+    }
+}
@@ -6,12 +6,16 @@ use std::path::Path;
 
 pub struct PolarisGpuFan {
     sysfs_pwm_file: PathBuf,
-    sysfs_pwm_enable_file: PathBuf
+    sysfs_pwm_enable_file: PathBuf,
+    sysfs_rpm_file: PathBuf,
+    sysfs_target_rpm_file: PathBuf
 }
 
-impl<'a> GenericSysFsFan for PolarisGpuFan {
+impl GenericSysFsFan for PolarisGpuFan {
     fn sysfs_pwm_file(&self) -> &PathBuf { &self.sysfs_pwm_file }
     fn sysfs_pwm_enable_file(&self) -> &PathBuf { &self.sysfs_pwm_enable_file }
+    fn sysfs_rpm_file(&self) -> &PathBuf { &self.sysfs_rpm_file }
+    fn sysfs_target_rpm_file(&self) -> &PathBuf { &self.sysfs_target_rpm_file }
 
     fn parse_mode(value: u8) -> FanMode {
         match value {
@@ -29,15 +33,68 @@ impl<'a> GenericSysFsFan for PolarisGpuFan {
     }
 }
 
-impl<'a> PolarisGpuFan {
+/// Highest pwm index looked at when discovering the fan
+const MAX_PWM_INDEX: u32 = 4;
+
+impl PolarisGpuFan {
     pub fn new<P: AsRef<Path>>(sysfs_dir: P, index: u32) -> Self {
+        Self::try_new(sysfs_dir, index).expect("Invalid pwm fan")
+    }
 
-        match build_sysfs_paths(sysfs_dir, index) {
-            Some((pwm_file, pwm_enable_file)) => PolarisGpuFan {
-                sysfs_pwm_file: pwm_file,
-                sysfs_pwm_enable_file: pwm_enable_file
-            },
-            None => panic!("Invalid pwm fan")
-        }
+    pub fn try_new<P: AsRef<Path>>(sysfs_dir: P, index: u32) -> Option<Self> {
+        build_sysfs_paths(sysfs_dir, index).map(|(pwm_file, pwm_enable_file, rpm_file, target_rpm_file)| PolarisGpuFan {
+            sysfs_pwm_file: pwm_file,
+            sysfs_pwm_enable_file: pwm_enable_file,
+            sysfs_rpm_file: rpm_file,
+            sysfs_target_rpm_file: target_rpm_file
+        })
+    }
+
+    /// First pwm with an enable file, most cards use pwm1 but some expose the fan at pwm2
+    pub fn discover<P: AsRef<Path>>(sysfs_dir: P) -> Option<Self> {
+        (1..=MAX_PWM_INDEX).find_map(|index| Self::try_new(&sysfs_dir, index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fan::FanControl;
+
+    #[test]
+    fn reads_firmware_duty_in_auto_mode() {
+        let hwmon_dir = std::env::temp_dir().join(format!("sentinel-fan-{}", std::process::id()));
+        std::fs::create_dir_all(&hwmon_dir).unwrap();
+        std::fs::write(hwmon_dir.join("pwm1"), "102\n").unwrap();
+        std::fs::write(hwmon_dir.join("pwm1_enable"), "2\n").unwrap();
+
+        let fan = PolarisGpuFan::new(&hwmon_dir, 1);
+
+        assert!(fan.mode() == FanMode::Auto);
+        assert!(fan.is_firmware_controlled());
+        assert_eq!(fan.speed().0, 40f64);
+        assert_eq!(fan.target_rpm(), None);
+
+        std::fs::write(hwmon_dir.join("fan1_target"), "1500\n").unwrap();
+        assert_eq!(fan.target_rpm(), Some(1500));
+
+        std::fs::write(hwmon_dir.join("pwm1_enable"), "1\n").unwrap();
+        assert!(!fan.is_firmware_controlled());
+
+        std::fs::remove_dir_all(&hwmon_dir).unwrap();
+    }
+
+    #[test]
+    fn discovers_fan_past_pwm1() {
+        let hwmon_dir = std::env::temp_dir().join(format!("sentinel-fan-discover-{}", std::process::id()));
+        std::fs::create_dir_all(&hwmon_dir).unwrap();
+        assert!(PolarisGpuFan::discover(&hwmon_dir).is_none());
+
+        std::fs::write(hwmon_dir.join("pwm2"), "0\n").unwrap();
+        std::fs::write(hwmon_dir.join("pwm2_enable"), "2\n").unwrap();
+        let fan = PolarisGpuFan::discover(&hwmon_dir).unwrap();
+        assert_eq!(fan.sysfs_pwm_file, hwmon_dir.join("pwm2"));
+
+        std::fs::remove_dir_all(&hwmon_dir).unwrap();
     }
 }
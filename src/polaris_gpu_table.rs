@@ -54,6 +54,17 @@ impl std::fmt::Display for PolarisGpuTable {
 
 impl PolarisGpuTable {
 
+    /// Table without any states, stands in for one the card doesn't expose
+    pub fn empty() -> PolarisGpuTable {
+        PolarisGpuTable {
+            voltage_range: 0..=0,
+            sclk_range: 0..=0,
+            mclk_range: 0..=0,
+            memory_states: Vec::new(),
+            core_states: Vec::new()
+        }
+    }
+
     pub fn voltage_range(&self) -> RangeInclusive::<u32> {
         RangeInclusive::new(*self.voltage_range.start(), *self.voltage_range.end())
     }
@@ -73,11 +84,22 @@ impl PolarisGpuTable {
         }
     }
 
+    pub fn last_state_index(&self, part: Part) -> Result<usize, StateInvalidReason> {
+        self.states(part).len().checked_sub(1).ok_or(StateInvalidReason::InvalidIndex)
+    }
+
+    /// Returns the existing state whose clock is closest to the one requested, clamped to the part's range
+    pub fn nearest_state(&self, part: Part, clock: u32) -> Option<PolarisGpuState> {
+        let range = self.clock_range(part);
+        let clock = clock.clamp(*range.start(), *range.end());
+
+        self.states(part).iter()
+            .min_by_key(|state| (state.clock as i64 - clock as i64).abs())
+            .copied()
+    }
+
     pub fn get_state(&self, part: Part, index: usize) -> Option<PolarisGpuState> {
-        match self.states(part).get(index) {
-            Some(state) => Some(state.clone()),
-            None => None
-        }
+        self.states(part).get(index).copied()
     }
 
     pub fn set_state(&mut self, part: Part, index: usize, state: PolarisGpuState) -> Result<(), StateInvalidReason> {
@@ -99,7 +121,87 @@ impl PolarisGpuTable {
         }
     }
 
-    pub fn validate_state<'a>(&self, part: Part, state: PolarisGpuState) -> Result<(), StateInvalidReason> {
+    /// Replaces the voltage of every state with the one at the same index in the other table
+    pub fn keep_voltages(&mut self, original: &PolarisGpuTable) {
+        for part in [Part::Core, Part::Memory].iter() {
+            let states = match part {
+                Part::Core => &mut self.core_states,
+                Part::Memory => &mut self.memory_states
+            };
+
+            for (state, original_state) in states.iter_mut().zip(original.states(*part).iter()) {
+                state.voltage = original_state.voltage;
+            }
+        }
+    }
+
+    /**
+        Lowers every state clocked above the limit down to it.
+        The advertised range is left alone, the driver refuses tables changing it.
+    **/
+    pub fn limit_clocks(&mut self, part: Part, max_clock: u32) {
+        let states = match part {
+            Part::Core => &mut self.core_states,
+            Part::Memory => &mut self.memory_states
+        };
+
+        for state in states.iter_mut() {
+            state.clock = state.clock.min(max_clock);
+        }
+    }
+
+    /// How far (mV) each state's voltage is above the bottom of the voltage range
+    pub fn voltage_headroom(&self, part: Part) -> Vec<u32> {
+        self.states(part).iter()
+            .map(|state| state.voltage.saturating_sub(*self.voltage_range.start()))
+            .collect()
+    }
+
+    /**
+        Rounds every clock and voltage to the nearest multiple of the steps the
+        driver accepts and clamps the result back into the ranges.
+    **/
+    pub fn quantize(&mut self, clock_step: u32, voltage_step: u32) {
+        let round = |value: u32, step: u32| {
+            let step = step.max(1);
+            (value + step / 2) / step * step
+        };
+
+        for part in [Part::Core, Part::Memory].iter() {
+            let quantized: Vec<PolarisGpuState> = self.states(*part).iter()
+                .map(|state| self.clamp_state(*part, PolarisGpuState {
+                    clock: round(state.clock, clock_step),
+                    voltage: round(state.voltage, voltage_step)
+                }))
+                .collect();
+
+            match part {
+                Part::Core => self.core_states = quantized,
+                Part::Memory => self.memory_states = quantized
+            }
+        }
+    }
+
+    /// Lowest state with its clock lowered to the bottom of the part's range, where that is below it
+    pub fn range_floor_state(&self, part: Part) -> Result<PolarisGpuState, StateInvalidReason> {
+        let lowest = self.get_state(part, 0).ok_or(StateInvalidReason::InvalidIndex)?;
+        let state = PolarisGpuState { clock: lowest.clock.min(*self.clock_range(part).start()), ..lowest };
+
+        self.validate_state(part, state)?;
+        Ok(state)
+    }
+
+    /// Moves the clock and voltage of the state into the part's ranges
+    pub fn clamp_state(&self, part: Part, state: PolarisGpuState) -> PolarisGpuState {
+        let clock_range = self.clock_range(part);
+
+        PolarisGpuState {
+            clock: state.clock.clamp(*clock_range.start(), *clock_range.end()),
+            voltage: state.voltage.clamp(*self.voltage_range.start(), *self.voltage_range.end())
+        }
+    }
+
+    pub fn validate_state(&self, part: Part, state: PolarisGpuState) -> Result<(), StateInvalidReason> {
         let clock_range = match part {
             Part::Core => &self.sclk_range,
             Part::Memory => &self.mclk_range
@@ -114,22 +216,65 @@ impl PolarisGpuTable {
         }
     }
 
-    fn parse_unit<'a>(data: &'a str, unit: &'static str) -> Option<u32> {
-        if data.ends_with(unit) {
-            match data.replace(unit, "").parse::<u32>() {
-                Ok(value) => Some(value),
-                Err(_) => None
-            }
+    /**
+        Parses a value with the expected unit suffix, ignoring its case.
+        Clocks may also be expressed in GHz and are converted to MHz.
+        Values without any unit are assumed to already be in the expected one.
+    **/
+    fn parse_unit(data: &str, unit: &'static str) -> Option<u32> {
+        let lowercase = data.trim().to_ascii_lowercase();
+
+        if let Some(value) = lowercase.strip_suffix(&unit.to_ascii_lowercase()) {
+            value.trim().parse::<u32>().ok()
+        } else if let Some(value) = lowercase.strip_suffix("ghz").filter(|_| unit == "MHz") {
+            value.trim().parse::<f64>().ok()
+                .filter(|ghz| *ghz >= 0f64)
+                .map(|ghz| (ghz * 1000f64).round() as u32)
+        } else if !lowercase.is_empty() && lowercase.chars().all(|c| c.is_ascii_digit()) {
+            println!("Value {} has no unit, assuming {}", data, unit);
+            lowercase.parse::<u32>().ok()
         } else {
             None
         }
     }
 
-    pub fn parse<'a>(data: &'a str) -> PolarisGpuTable {
+    fn range_prefix(part: Option<Part>) -> &'static str {
+        match part {
+            Some(Part::Core) => "SCLK",
+            Some(Part::Memory) => "MCLK",
+            None => "VDDC"
+        }
+    }
+
+    /**
+        Parses only the OD_RANGE section, so ranges are available even when
+        the state sections can't be parsed. A `None` part means the voltage range.
+    **/
+    pub fn try_parse_range(data: &str, part: Option<Part>) -> Option<RangeInclusive<u32>> {
+        let prefix = Self::range_prefix(part);
+        let unit = match part {
+            Some(_) => "MHz",
+            None => "mV"
+        };
+
+        data.split('\n')
+            .map(|line| line.trim())
+            .skip_while(|line| !line.starts_with("OD_RANGE"))
+            .filter_map(|line| line.strip_prefix(prefix))
+            .filter_map(|line| line.strip_prefix(':'))
+            .find_map(|bounds| {
+                let mut split = bounds.split_whitespace();
+                let lower = Self::parse_unit(split.next()?, unit)?;
+                let upper = Self::parse_unit(split.next()?, unit)?;
+                Some(RangeInclusive::new(lower, upper))
+            })
+    }
+
+    pub fn parse(data: &str) -> PolarisGpuTable {
         Self::try_parse(data).expect("Failed to parse PolarisGpuTable")
     }
 
-    pub fn try_parse<'a>(data: &'a str) -> Option<PolarisGpuTable> {
+    pub fn try_parse(data: &str) -> Option<PolarisGpuTable> {
         let mut voltage_range: Option<RangeInclusive::<u32>> = None;
         let mut sclk_range: Option<RangeInclusive::<u32>> = None;
         let mut mclk_range: Option<RangeInclusive::<u32>> = None;
@@ -141,7 +286,7 @@ impl PolarisGpuTable {
             Core,
             Memory,
             Ranges
-        };
+        }
         let mut state = ParserState::Initial;
         for line in data.split("\n") {
             let mut semicolon_split = line.trim().split(":");
@@ -154,16 +299,14 @@ impl PolarisGpuTable {
                 None => ""
             };
 
-            if data != "" {
+            if !data.is_empty() {
                 let mut data_split = data.split_whitespace();
                 match state {
-                    ParserState::Initial => panic!("Don't know what I'm parsing"),
+                    // Data before any known section
+                    ParserState::Initial => return None,
                     ParserState::Core | ParserState::Memory => {
-                        let clock_str = data_split.next().expect("No clock");
-                        let voltage_str = data_split.next().expect("No voltage");
-
-                        let clock = Self::parse_unit(&clock_str, "MHz").expect("Invalid clock value");
-                        let voltage = Self::parse_unit(&voltage_str, "mV").expect("Invalid voltage value");
+                        let clock = Self::parse_unit(data_split.next()?, "MHz")?;
+                        let voltage = Self::parse_unit(data_split.next()?, "mV")?;
 
                         let states = match state {
                             ParserState::Core => &mut core_states,
@@ -173,25 +316,21 @@ impl PolarisGpuTable {
                         states.push(PolarisGpuState { clock, voltage });
                     },
                     ParserState::Ranges => {
-                        let lower_str = data_split.next().expect("No lower voltage bound");
-                        let upper_str = data_split.next().expect("No upper voltage bound");
-
                         let unit = match prefix {
                             "SCLK" | "MCLK" => "MHz",
                             "VDDC" => "mV",
-                            _ => panic!("Unknown range target")
+                            _ => return None
                         };
 
-                        let lower = Self::parse_unit(&lower_str, unit).expect("Invalid lower range bound");
-                        let upper = Self::parse_unit(&upper_str, unit).expect("Invalid upper range bound");
+                        let lower = Self::parse_unit(data_split.next()?, unit)?;
+                        let upper = Self::parse_unit(data_split.next()?, unit)?;
 
                         let range = RangeInclusive::new(lower, upper);
 
                         match prefix {
                             "SCLK" => sclk_range = Some(range),
                             "MCLK" => mclk_range = Some(range),
-                            "VDDC" => voltage_range = Some(range),
-                            _ => panic!("Unknown range target")
+                            _ => voltage_range = Some(range)
                         };
                     }
                 }
@@ -201,22 +340,23 @@ impl PolarisGpuTable {
                     "OD_MCLK" => state = ParserState::Memory,
                     "OD_RANGE" => state = ParserState::Ranges,
                     "" => continue,
-                    _ => panic!(format!("Unknown prefix {}", prefix))
+                    _ => return None
                 }
             }
         }
 
-        if voltage_range.is_some() && sclk_range.is_some() && mclk_range.is_some() &&
-            memory_states.len() > 0 && core_states.len() > 0
-        { 
-            Some(PolarisGpuTable { 
-                voltage_range:  voltage_range.unwrap(),
-                sclk_range:  sclk_range.unwrap(),
-                mclk_range:  mclk_range.unwrap(),
-                memory_states,
-                core_states})
-        } else {
-            None
+        match (voltage_range, sclk_range, mclk_range) {
+            (Some(voltage_range), Some(sclk_range), Some(mclk_range))
+                if !memory_states.is_empty() && !core_states.is_empty() =>
+            {
+                Some(PolarisGpuTable {
+                    voltage_range,
+                    sclk_range,
+                    mclk_range,
+                    memory_states,
+                    core_states})
+            },
+            _ => None
         }
     }
 
@@ -249,7 +389,7 @@ mod tests {
         VDDC:     750mV        1150mV\n\
         ";
 
-        let table = PolarisGpuTable::parse(&data);
+        let table = PolarisGpuTable::parse(data);
         assert_eq!(table.voltage_range(), RangeInclusive::new(750, 1150));
         assert_eq!(table.clock_range(Part::Core), RangeInclusive::new(300, 2000));
         assert_eq!(table.clock_range(Part::Memory), RangeInclusive::new(300, 2250));
@@ -278,4 +418,203 @@ mod tests {
         assert_eq!(mstates[2].clock, 1500);
         assert_eq!(mstates[2].voltage, 900);
     }
+
+    #[test]
+    fn parses_clock_in_ghz() {
+        use super::*;
+
+        assert_eq!(PolarisGpuTable::parse_unit("1.85GHz", "MHz"), Some(1850));
+        assert_eq!(PolarisGpuTable::parse_unit("2Ghz", "MHz"), Some(2000));
+        assert_eq!(PolarisGpuTable::parse_unit("1.1GHz", "mV"), None);
+    }
+
+    #[test]
+    fn parses_lowercase_units() {
+        use super::*;
+
+        assert_eq!(PolarisGpuTable::parse_unit("1244mhz", "MHz"), Some(1244));
+        assert_eq!(PolarisGpuTable::parse_unit("1150mv", "mV"), Some(1150));
+        assert_eq!(PolarisGpuTable::parse_unit("1150MV", "mV"), Some(1150));
+    }
+
+    #[test]
+    fn parses_unitless_values() {
+        use super::*;
+
+        assert_eq!(PolarisGpuTable::parse_unit("300", "MHz"), Some(300));
+        assert_eq!(PolarisGpuTable::parse_unit("750", "mV"), Some(750));
+        assert_eq!(PolarisGpuTable::parse_unit("abc", "mV"), None);
+    }
+
+    #[test]
+    fn finds_nearest_state() {
+        use super::*;
+
+        let table = PolarisGpuTable::parse("OD_SCLK:\n\
+        0: 300MHz 750mV\n\
+        OD_MCLK:\n\
+        0: 300MHz 750mV\n\
+        1: 1000MHz 800mV\n\
+        2: 1750MHz 900mV\n\
+        OD_RANGE:\n\
+        SCLK: 300MHz 2000MHz\n\
+        MCLK: 300MHz 2250MHz\n\
+        VDDC: 750mV 1150mV\n");
+
+        assert_eq!(table.nearest_state(Part::Memory, 1100).unwrap().clock, 1000);
+        assert_eq!(table.nearest_state(Part::Memory, 1500).unwrap().clock, 1750);
+        assert_eq!(table.nearest_state(Part::Memory, 0).unwrap().clock, 300);
+        assert_eq!(table.nearest_state(Part::Memory, 5000).unwrap().clock, 1750);
+    }
+
+    #[test]
+    fn parses_ranges_with_malformed_states() {
+        use super::*;
+
+        let data = "OD_SCLK:\n\
+        0: garbage\n\
+        OD_MCLK:\n\
+        OD_RANGE:\n\
+        SCLK:     300MHz       2000MHz\n\
+        MCLK:     300MHz       2250MHz\n\
+        VDDC:     750mV        1150mV\n";
+
+        assert_eq!(PolarisGpuTable::try_parse_range(data, Some(Part::Core)), Some(RangeInclusive::new(300, 2000)));
+        assert_eq!(PolarisGpuTable::try_parse_range(data, Some(Part::Memory)), Some(RangeInclusive::new(300, 2250)));
+        assert_eq!(PolarisGpuTable::try_parse_range(data, None), Some(RangeInclusive::new(750, 1150)));
+        assert_eq!(PolarisGpuTable::try_parse_range("OD_SCLK:\n0: 300MHz 750mV\n", None), None);
+    }
+
+    #[test]
+    fn keeps_original_voltages() {
+        use super::*;
+
+        let original = PolarisGpuTable::parse("OD_SCLK:\n\
+        0: 300MHz 750mV\n\
+        1: 1000MHz 900mV\n\
+        OD_MCLK:\n\
+        0: 300MHz 750mV\n\
+        OD_RANGE:\n\
+        SCLK: 300MHz 2000MHz\n\
+        MCLK: 300MHz 2250MHz\n\
+        VDDC: 750mV 1150mV\n");
+
+        let mut table = original.clone();
+        table.set_state(Part::Core, 1, PolarisGpuState { clock: 1100, voltage: 800 }).unwrap();
+        table.keep_voltages(&original);
+
+        assert_eq!(table.get_state(Part::Core, 1), Some(PolarisGpuState { clock: 1100, voltage: 900 }));
+    }
+
+    #[test]
+    fn limits_clocks_without_touching_range() {
+        use super::*;
+
+        let mut table = PolarisGpuTable::parse("OD_SCLK:\n\
+        0: 300MHz 750mV\n\
+        1: 1400MHz 1000mV\n\
+        OD_MCLK:\n\
+        0: 2000MHz 900mV\n\
+        OD_RANGE:\n\
+        SCLK: 300MHz 2000MHz\n\
+        MCLK: 300MHz 2250MHz\n\
+        VDDC: 750mV 1150mV\n");
+
+        table.limit_clocks(Part::Core, 1300);
+
+        assert_eq!(table.get_state(Part::Core, 0), Some(PolarisGpuState { clock: 300, voltage: 750 }));
+        assert_eq!(table.get_state(Part::Core, 1), Some(PolarisGpuState { clock: 1300, voltage: 1000 }));
+        assert_eq!(table.get_state(Part::Memory, 0), Some(PolarisGpuState { clock: 2000, voltage: 900 }));
+        assert_eq!(table.clock_range(Part::Core), RangeInclusive::new(300, 2000));
+    }
+
+    #[test]
+    fn computes_voltage_headroom() {
+        use super::*;
+
+        let table = PolarisGpuTable::parse("OD_SCLK:\n0: 300MHz 750mV\n1: 1250MHz 1025mV\n\
+            OD_MCLK:\n0: 300MHz 800mV\n\
+            OD_RANGE:\nSCLK: 300MHz 2000MHz\nMCLK: 300MHz 2250MHz\nVDDC: 750mV 1150mV\n");
+
+        assert_eq!(table.voltage_headroom(Part::Core), vec![0, 275]);
+        assert_eq!(table.voltage_headroom(Part::Memory), vec![50]);
+    }
+
+    #[test]
+    fn quantizes_into_steps_and_ranges() {
+        use super::*;
+
+        let mut table = PolarisGpuTable::parse("OD_SCLK:\n0: 303MHz 752mV\n1: 1248MHz 1018mV\n\
+            OD_MCLK:\n0: 2249MHz 800mV\n\
+            OD_RANGE:\nSCLK: 300MHz 2000MHz\nMCLK: 300MHz 2250MHz\nVDDC: 750mV 1150mV\n");
+        table.quantize(5, 25);
+
+        assert_eq!(table.get_state(Part::Core, 0), Some(PolarisGpuState { clock: 305, voltage: 750 }));
+        assert_eq!(table.get_state(Part::Core, 1), Some(PolarisGpuState { clock: 1250, voltage: 1025 }));
+        assert_eq!(table.get_state(Part::Memory, 0), Some(PolarisGpuState { clock: 2250, voltage: 800 }));
+    }
+
+    #[test]
+    fn unknown_format_is_not_parsed() {
+        use super::*;
+
+        assert_eq!(PolarisGpuTable::try_parse("GFXCLK:\n0: 300MHz\n"), None);
+        assert_eq!(PolarisGpuTable::try_parse("OD_SCLK:\n0: 300MHz\n"), None);
+    }
+
+    #[test]
+    fn clamps_state_into_ranges() {
+        use super::*;
+
+        let table = PolarisGpuTable::parse("OD_SCLK:\n\
+        0: 300MHz 750mV\n\
+        OD_MCLK:\n\
+        0: 300MHz 750mV\n\
+        OD_RANGE:\n\
+        SCLK: 300MHz 2000MHz\n\
+        MCLK: 300MHz 2250MHz\n\
+        VDDC: 750mV 1150mV\n");
+
+        assert_eq!(table.clamp_state(Part::Core, PolarisGpuState { clock: 2100, voltage: 700 }),
+            PolarisGpuState { clock: 2000, voltage: 750 });
+        assert_eq!(table.clamp_state(Part::Memory, PolarisGpuState { clock: 1750, voltage: 900 }),
+            PolarisGpuState { clock: 1750, voltage: 900 });
+    }
+
+    #[test]
+    fn lowers_lowest_state_to_range_floor() {
+        use super::*;
+
+        let table = PolarisGpuTable::parse("OD_SCLK:\n\
+        0: 300MHz 750mV\n\
+        OD_MCLK:\n\
+        0: 400MHz 800mV\n\
+        1: 1750MHz 900mV\n\
+        OD_RANGE:\n\
+        SCLK: 300MHz 2000MHz\n\
+        MCLK: 300MHz 2250MHz\n\
+        VDDC: 750mV 1150mV\n");
+
+        assert_eq!(table.range_floor_state(Part::Memory), Ok(PolarisGpuState { clock: 300, voltage: 800 }));
+        assert_eq!(table.range_floor_state(Part::Core), Ok(PolarisGpuState { clock: 300, voltage: 750 }));
+    }
+
+    #[test]
+    fn empty_part_has_no_valid_index() {
+        use super::*;
+
+        let state = PolarisGpuState { clock: 300, voltage: 750 };
+        let mut table = PolarisGpuTable {
+            voltage_range: RangeInclusive::new(750, 1150),
+            sclk_range: RangeInclusive::new(300, 2000),
+            mclk_range: RangeInclusive::new(300, 2250),
+            memory_states: vec![],
+            core_states: vec![state]
+        };
+
+        assert_eq!(table.last_state_index(Part::Core), Ok(0));
+        assert_eq!(table.last_state_index(Part::Memory), Err(StateInvalidReason::InvalidIndex));
+        assert_eq!(table.get_state(Part::Memory, 0), None);
+        assert_eq!(table.set_state(Part::Memory, 0, state), Err(StateInvalidReason::InvalidIndex));
+    }
 }
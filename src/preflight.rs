@@ -0,0 +1,38 @@
+use crate::config::EmergencySensorMode;
+
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+/// A failed startup precondition, see GpuStateMachine::preflight
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreflightError {
+    /// Along with the feature which needs it
+    MissingNode(PathBuf, &'static str),
+    NotWritable(PathBuf),
+    NoEmergencySensor(EmergencySensorMode),
+    NoFanControl,
+    PowerRangeUnreadable,
+    PowerLimitOutOfRange {
+        name: &'static str,
+        limit: f32,
+        range: RangeInclusive<f32>
+    }
+}
+
+impl std::fmt::Display for PreflightError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PreflightError::MissingNode(path, feature) =>
+                write!(f, "{:?} is missing, it's required for {}", path, feature),
+            PreflightError::NotWritable(path) => write!(f, "{:?} can't be opened for writing, run as root", path),
+            PreflightError::NoEmergencySensor(mode) =>
+                write!(f, "No sensor for emergency_sensor_mode {:?} can be read, the Emergency cutoff would be blind", mode),
+            PreflightError::NoFanControl => write!(f, "No controllable fan found, drop FanControl to leave the fan to the firmware"),
+            PreflightError::PowerRangeUnreadable => write!(f, "The power limit range (power1_cap_min, power1_cap_max) can't be read"),
+            PreflightError::PowerLimitOutOfRange { name, limit, range } =>
+                write!(f, "{} of {}W is outside the card's range of {}W to {}W", name, limit, range.start(), range.end())
+        }
+    }
+
+}
@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::Path;
+
+/**
+    Whether a process with the given name runs, matched against /proc/<pid>/comm
+    and the file name of argv[0] since comm is cut at 15 characters.
+**/
+pub fn is_running(name: &str) -> bool {
+    any_running(&[name])
+}
+
+/// Whether a process with any of the names runs, in a single pass over /proc
+pub fn any_running(names: &[&str]) -> bool {
+    is_running_in(Path::new("/proc"), names)
+}
+
+fn is_running_in(proc_dir: &Path, names: &[&str]) -> bool {
+    let entries = match fs::read_dir(proc_dir) {
+        Ok(entries) => entries,
+        Err(_) => return false
+    };
+
+    entries.flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().bytes().all(|byte| byte.is_ascii_digit()))
+        .any(|entry| {
+            let comm = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+            let cmdline = fs::read(entry.path().join("cmdline")).unwrap_or_default();
+            let argv0 = cmdline.split(|byte| *byte == 0).next().unwrap_or(&[]);
+            let argv0 = String::from_utf8_lossy(argv0);
+
+            let argv0 = Path::new(argv0.as_ref()).file_name();
+
+            names.iter().any(|name| comm.trim_end() == *name || argv0.is_some_and(|file| file == *name))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_process_by_comm_or_argv0() {
+        let proc_dir = std::env::temp_dir().join(format!("sentinel-proc-{}", std::process::id()));
+        fs::create_dir_all(proc_dir.join("42")).unwrap();
+        fs::create_dir_all(proc_dir.join("self")).unwrap();
+        fs::write(proc_dir.join("42/comm"), "Main\n").unwrap();
+        fs::write(proc_dir.join("42/cmdline"), "/opt/game/bin/SomeLongGameName.x86_64\0-fullscreen\0").unwrap();
+        fs::write(proc_dir.join("self/comm"), "steam\n").unwrap();
+
+        assert!(is_running_in(&proc_dir, &["Main"]));
+        assert!(is_running_in(&proc_dir, &["SomeLongGameName.x86_64"]));
+        assert!(!is_running_in(&proc_dir, &["steam"]));
+        assert!(is_running_in(&proc_dir, &["xmrig", "Main"]));
+
+        fs::remove_dir_all(&proc_dir).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+use crate::clamped_percentage::ClampedPercentage;
+
+/// RPM error below which the duty is left alone, the tachometer jitters by about this much
+const RPM_DEADBAND: f64 = 50.0;
+
+/**
+    Holds the fan at an RPM instead of a duty by nudging the pwm towards it
+    on every step. Speeds are read as a share of full_speed RPM, so curves
+    keep their percentages but mean the same loudness on any fan.
+**/
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpmController {
+    full_speed: u32,
+    /// Duty (percentage points) added per RPM below the target
+    gain: f64
+}
+
+impl RpmController {
+    pub fn new(full_speed: u32, gain: f64) -> Self {
+        RpmController { full_speed, gain }
+    }
+
+    pub fn target_rpm(&self, speed: ClampedPercentage) -> u32 {
+        (speed.0 / 100f64 * self.full_speed as f64).round() as u32
+    }
+
+    /// Duty (%) to write after duty got the fan to rpm, heading for speed
+    pub fn next(&self, duty: f64, rpm: u32, speed: ClampedPercentage) -> f64 {
+        let target = self.target_rpm(speed);
+        if target == 0 {
+            return 0f64;
+        }
+
+        let error = target as f64 - rpm as f64;
+        if error.abs() < RPM_DEADBAND {
+            return duty;
+        }
+
+        (duty + self.gain * error).clamp(0f64, 100f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settles_on_target_rpm() {
+        let controller = RpmController::new(3000, 0.01);
+        // A fan doing 30 RPM per percent of duty
+        let fan = |duty: f64| (duty * 30f64) as u32;
+
+        let mut duty = 20f64;
+        for _ in 0..50 {
+            duty = controller.next(duty, fan(duty), ClampedPercentage::new(50));
+        }
+
+        assert!(fan(duty).abs_diff(1500) < RPM_DEADBAND as u32);
+        assert_eq!(controller.next(duty, fan(duty), ClampedPercentage::new(0)), 0f64);
+    }
+}
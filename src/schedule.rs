@@ -0,0 +1,83 @@
+use crate::performance_level::PerformanceLevel;
+
+use serde::{Deserialize, Serialize};
+
+/// Forces a performance level from start until end, local "HH:MM" times which may wrap past midnight
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledLevel {
+    pub start: String,
+    pub end: String,
+    pub performance_level: PerformanceLevel
+}
+
+/// Minutes since midnight of a "HH:MM" time
+pub fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+
+    if hours < 24 && minutes < 60 {
+        Some(hours * 60 + minutes)
+    } else {
+        None
+    }
+}
+
+/// Minutes since local midnight
+pub fn local_minute_of_day() -> u32 {
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut local: libc::tm = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::localtime_r(&now, &mut local) }.is_null() {
+        // Falls back to UTC which is at least consistent
+        return ((now / 60) % (24 * 60)) as u32;
+    }
+    (local.tm_hour * 60 + local.tm_min) as u32
+}
+
+impl ScheduledLevel {
+    pub fn is_valid(&self) -> bool {
+        parse_time(&self.start).is_some() && parse_time(&self.end).is_some()
+    }
+
+    pub fn contains(&self, minute: u32) -> bool {
+        match (parse_time(&self.start), parse_time(&self.end)) {
+            (Some(start), Some(end)) if start <= end => start <= minute && minute < end,
+            (Some(start), Some(end)) => minute >= start || minute < end,
+            _ => false
+        }
+    }
+}
+
+/// Level of the first window containing minute
+pub fn scheduled_level(schedule: &[ScheduledLevel], minute: u32) -> Option<PerformanceLevel> {
+    schedule.iter()
+        .find(|window| window.contains(minute))
+        .map(|window| window.performance_level.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(start: &str, end: &str) -> ScheduledLevel {
+        ScheduledLevel { start: start.to_string(), end: end.to_string(), performance_level: PerformanceLevel::Low }
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_time("07:30"), Some(450));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("7"), None);
+    }
+
+    #[test]
+    fn windows_wrap_past_midnight() {
+        let overnight = [window("23:00", "07:00")];
+
+        assert_eq!(scheduled_level(&overnight, 23 * 60 + 30), Some(PerformanceLevel::Low));
+        assert_eq!(scheduled_level(&overnight, 6 * 60), Some(PerformanceLevel::Low));
+        assert_eq!(scheduled_level(&overnight, 7 * 60), None);
+        assert_eq!(scheduled_level(&[window("09:00", "17:00")], 8 * 60), None);
+    }
+}
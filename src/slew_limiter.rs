@@ -0,0 +1,92 @@
+use std::time::Instant;
+
+/**
+    Limits how fast a value may change per second of real time.
+    Rising and falling rates are independent, `None` means unlimited.
+**/
+#[derive(Debug, Clone)]
+pub struct SlewLimiter {
+    rise_per_sec: Option<f64>,
+    fall_per_sec: Option<f64>,
+    last: Option<(f64, Instant)>
+}
+
+impl SlewLimiter {
+    pub fn new(rise_per_sec: Option<f64>, fall_per_sec: Option<f64>) -> Self {
+        SlewLimiter { rise_per_sec, fall_per_sec, last: None }
+    }
+
+    pub fn symmetric(per_sec: Option<f64>) -> Self {
+        Self::new(per_sec, per_sec)
+    }
+
+    pub fn is_seeded(&self) -> bool {
+        self.last.is_some()
+    }
+
+    pub fn seed(&mut self, value: f64, now: Instant) {
+        self.last = Some((value, now));
+    }
+
+    pub fn last(&self) -> Option<f64> {
+        self.last.map(|(value, _)| value)
+    }
+
+    /**
+        Returns the value to use now when heading towards target.
+        Without a previous value the target is returned as is.
+    **/
+    pub fn next(&mut self, target: f64, now: Instant) -> f64 {
+        let value = match self.last {
+            None => target,
+            Some((last, at)) => {
+                let dt = now.saturating_duration_since(at).as_secs_f64();
+                let delta = target - last;
+                let rate = if delta > 0f64 { self.rise_per_sec } else { self.fall_per_sec };
+
+                match rate {
+                    Some(rate) => last + delta.clamp(-rate * dt, rate * dt),
+                    None => target
+                }
+            }
+        };
+
+        self.last = Some((value, now));
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn unlimited_jumps_to_target() {
+        let now = Instant::now();
+        let mut limiter = SlewLimiter::symmetric(None);
+
+        limiter.seed(0f64, now);
+        assert_eq!(limiter.next(100f64, now + Duration::from_secs(1)), 100f64);
+    }
+
+    #[test]
+    fn limits_change_by_elapsed_time() {
+        let now = Instant::now();
+        let mut limiter = SlewLimiter::symmetric(Some(5f64));
+
+        limiter.seed(20f64, now);
+        assert_eq!(limiter.next(100f64, now + Duration::from_secs(2)), 30f64);
+        assert_eq!(limiter.next(0f64, now + Duration::from_secs(3)), 25f64);
+    }
+
+    #[test]
+    fn rise_and_fall_are_independent() {
+        let now = Instant::now();
+        let mut limiter = SlewLimiter::new(Some(1f64), None);
+
+        limiter.seed(50f64, now);
+        assert_eq!(limiter.next(60f64, now + Duration::from_secs(1)), 51f64);
+        assert_eq!(limiter.next(10f64, now + Duration::from_secs(2)), 10f64);
+    }
+}
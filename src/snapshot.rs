@@ -0,0 +1,228 @@
+use crate::GpuCustomState;
+use crate::clamped_percentage::ClampedPercentage;
+use crate::polaris_gpu::format_clock;
+
+use serde::Serialize;
+
+use std::fmt;
+
+/// Latest readings and decisions of the state machine, missing readings are None
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GpuSnapshot {
+    pub state: GpuCustomState,
+    /// Control temperature, the edge one blended with junction_weight of the junction one
+    pub temperature: Option<f32>,
+    pub weighted_temperature: Option<f32>,
+    pub junction_temperature: Option<f32>,
+    pub memory_temperature: Option<f32>,
+    pub usage: Option<f64>,
+    pub weighted_usage: Option<f64>,
+    pub power_usage: Option<f32>,
+    pub fan_speed: Option<ClampedPercentage>,
+    pub fan_rpm: Option<u32>,
+    /// RPM the RPM controller or the firmware aims for, the latter only exposed by some amdgpu versions
+    pub fan_target_rpm: Option<u32>,
+    /// Rough fan loudness (dBA) scaled from noise_reference_rpm
+    pub fan_noise: Option<f32>,
+    pub fan_firmware_controlled: bool,
+    /// Signs of fan wear, see FanHealth
+    pub fan_health: Option<String>,
+    /// Why the state is held, see GpuStateMachine::transition_block_reason
+    pub transition_block: Option<String>,
+    pub core_clock: Option<u32>,
+    pub memory_clock: Option<u32>,
+    /// Measured core voltage (mV)
+    pub core_voltage: Option<u32>,
+    /// Core voltage (mV) of the current pstate in the table
+    pub table_voltage: Option<u32>,
+    /// The two are further apart than voltage_discrepancy_margin
+    pub voltage_diverged: bool,
+    /// Correctable and uncorrectable memory errors since startup, None without RAS support
+    pub memory_errors: Option<(u64, u64)>
+}
+
+fn or_unknown<T: fmt::Display>(value: &Option<T>, unit: &str) -> String {
+    match value {
+        Some(value) => format!("{}{}", value, unit),
+        None => "?".to_string()
+    }
+}
+
+/// A field which differs between two snapshots, values are already formatted
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub name: &'static str,
+    pub old: String,
+    pub new: String
+}
+
+impl fmt::Display for FieldChange {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.name, self.old, self.new)
+    }
+
+}
+
+fn float_changed(old: Option<f64>, new: Option<f64>, tolerance: f64) -> bool {
+    match (old, new) {
+        (Some(old), Some(new)) => (old - new).abs() > tolerance,
+        (old, new) => old.is_some() != new.is_some()
+    }
+}
+
+impl GpuSnapshot {
+    /**
+        Lists the fields which moved between self and other.
+        Float readings only count as changed when they differ by more than tolerance.
+    **/
+    pub fn diff(&self, other: &Self, tolerance: f64) -> Vec<FieldChange> {
+        let mut changes = Vec::new();
+
+        let mut exact = |name: &'static str, old: String, new: String| {
+            if old != new {
+                changes.push(FieldChange { name, old, new });
+            }
+        };
+        exact("state", format!("{:?}", self.state), format!("{:?}", other.state));
+        exact("fan_rpm", or_unknown(&self.fan_rpm, ""), or_unknown(&other.fan_rpm, ""));
+        exact("fan_target_rpm", or_unknown(&self.fan_target_rpm, ""), or_unknown(&other.fan_target_rpm, ""));
+        exact("fan_firmware_controlled", self.fan_firmware_controlled.to_string(), other.fan_firmware_controlled.to_string());
+        exact("fan_health", or_unknown(&self.fan_health, ""), or_unknown(&other.fan_health, ""));
+        exact("transition_block", or_unknown(&self.transition_block, ""), or_unknown(&other.transition_block, ""));
+        exact("core_clock", or_unknown(&self.core_clock, ""), or_unknown(&other.core_clock, ""));
+        exact("memory_clock", or_unknown(&self.memory_clock, ""), or_unknown(&other.memory_clock, ""));
+        exact("core_voltage", or_unknown(&self.core_voltage, ""), or_unknown(&other.core_voltage, ""));
+        exact("table_voltage", or_unknown(&self.table_voltage, ""), or_unknown(&other.table_voltage, ""));
+        let errors = |errors: Option<(u64, u64)>| or_unknown(&errors.map(|(ce, ue)| format!("{} ce, {} ue", ce, ue)), "");
+        exact("memory_errors", errors(self.memory_errors), errors(other.memory_errors));
+
+        let floats = [
+            ("temperature", self.temperature.map(f64::from), other.temperature.map(f64::from)),
+            ("weighted_temperature", self.weighted_temperature.map(f64::from), other.weighted_temperature.map(f64::from)),
+            ("junction_temperature", self.junction_temperature.map(f64::from), other.junction_temperature.map(f64::from)),
+            ("memory_temperature", self.memory_temperature.map(f64::from), other.memory_temperature.map(f64::from)),
+            ("usage", self.usage, other.usage),
+            ("weighted_usage", self.weighted_usage, other.weighted_usage),
+            ("power_usage", self.power_usage.map(f64::from), other.power_usage.map(f64::from)),
+            ("fan_speed", self.fan_speed.map(|speed| speed.0), other.fan_speed.map(|speed| speed.0)),
+            ("fan_noise", self.fan_noise.map(f64::from), other.fan_noise.map(f64::from))
+        ];
+        for (name, old, new) in floats.iter() {
+            if float_changed(*old, *new, tolerance) {
+                changes.push(FieldChange { name, old: or_unknown(old, ""), new: or_unknown(new, "") });
+            }
+        }
+
+        changes
+    }
+
+    /**
+        Whether anything moved since the last logged snapshot worth another
+        status line: the state, the temperature crossing a whole degree or
+        the fan speed changing by more than fan_speed_step.
+    **/
+    pub fn worth_logging(&self, last: &Self, fan_speed_step: f64) -> bool {
+        let degree = |snapshot: &Self| snapshot.temperature.map(|temperature| temperature.floor() as i32);
+
+        degree(self) != degree(last) || last.diff(self, fan_speed_step).iter()
+            .any(|change| ["state", "fan_speed", "fan_firmware_controlled"].contains(&change.name))
+    }
+}
+
+impl fmt::Display for GpuSnapshot {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.transition_block {
+            Some(reason) => writeln!(f, "State:       {:?} ({})", self.state, reason)?,
+            None => writeln!(f, "State:       {:?}", self.state)?
+        }
+        writeln!(f, "Temperature: {} (weighted {})", or_unknown(&self.temperature, "C"),
+            or_unknown(&self.weighted_temperature.map(|t| format!("{:.2}", t)), "C"))?;
+        if self.junction_temperature.is_some() || self.memory_temperature.is_some() {
+            writeln!(f, "             {} junction, {} memory", or_unknown(&self.junction_temperature, "C"),
+                or_unknown(&self.memory_temperature, "C"))?;
+        }
+        writeln!(f, "Usage:       {} (weighted {})", or_unknown(&self.usage, "%"),
+            or_unknown(&self.weighted_usage.map(|u| format!("{:.2}", u)), "%"))?;
+        writeln!(f, "Power:       {}", or_unknown(&self.power_usage.map(|p| format!("{:.2}", p)), "W"))?;
+        writeln!(f, "Clocks:      {} core, {} memory", format_clock(self.core_clock), format_clock(self.memory_clock))?;
+        if self.core_voltage.is_some() {
+            writeln!(f, "Voltage:     {} measured, {} in the table{}", or_unknown(&self.core_voltage, "mV"),
+                or_unknown(&self.table_voltage, "mV"), if self.voltage_diverged { " (diverged)" } else { "" })?;
+        }
+        if let Some((correctable, uncorrectable)) = self.memory_errors {
+            writeln!(f, "Memory:      {} correctable, {} uncorrectable errors since startup", correctable, uncorrectable)?;
+        }
+        let target = self.fan_target_rpm.map_or(String::new(), |rpm| format!(", target {}", rpm));
+        let noise = self.fan_noise.map_or(String::new(), |dba| format!(", ~{:.0} dBA", dba));
+        write!(f, "Fan:         {} ({} RPM{}{}){}", or_unknown(&self.fan_speed, ""), or_unknown(&self.fan_rpm, ""), target, noise,
+            if self.fan_firmware_controlled { ", firmware controlled" } else { "" })?;
+        if let Some(warning) = &self.fan_health {
+            write!(f, "\n             {}", warning)?;
+        }
+        Ok(())
+    }
+
+}
+
+/// An idle card, tests override the fields they care about
+#[cfg(test)]
+pub(crate) fn snapshot() -> GpuSnapshot {
+    GpuSnapshot {
+        state: GpuCustomState::Idle,
+        temperature: Some(45f32),
+        weighted_temperature: Some(44f32),
+        junction_temperature: None,
+        memory_temperature: None,
+        usage: Some(0f64),
+        weighted_usage: Some(0f64),
+        power_usage: Some(10f32),
+        fan_speed: None,
+        fan_rpm: Some(0),
+        fan_target_rpm: None,
+        fan_noise: None,
+        fan_firmware_controlled: false,
+        fan_health: None,
+        transition_block: None,
+        core_clock: Some(300),
+        memory_clock: None,
+        core_voltage: None,
+        table_voltage: None,
+        voltage_diverged: false,
+        memory_errors: None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_changed_fields_within_tolerance() {
+        let old = snapshot();
+        let mut new = snapshot();
+        new.state = GpuCustomState::Performance;
+        new.temperature = Some(45.3f32);
+        new.power_usage = Some(80f32);
+        new.memory_clock = Some(1750);
+
+        let changes = old.diff(&new, 0.5);
+        let names: Vec<_> = changes.iter().map(|change| change.name).collect();
+
+        assert_eq!(names, vec!["state", "memory_clock", "power_usage"]);
+        assert_eq!(changes[1].to_string(), "memory_clock: ? -> 1750");
+        assert!(old.diff(&old, 0f64).is_empty());
+    }
+
+    #[test]
+    fn logs_only_meaningful_changes() {
+        let last = GpuSnapshot { temperature: Some(45.2f32), fan_speed: Some(ClampedPercentage::new(30)), ..snapshot() };
+
+        assert!(!GpuSnapshot { temperature: Some(45.9f32), power_usage: Some(12f32), ..last.clone() }.worth_logging(&last, 5f64));
+        assert!(!GpuSnapshot { fan_speed: Some(ClampedPercentage::new(34)), ..last.clone() }.worth_logging(&last, 5f64));
+        assert!(GpuSnapshot { fan_speed: Some(ClampedPercentage::new(36)), ..last.clone() }.worth_logging(&last, 5f64));
+        assert!(GpuSnapshot { temperature: Some(46.1f32), ..last.clone() }.worth_logging(&last, 5f64));
+        assert!(GpuSnapshot { state: GpuCustomState::CoolOff, ..last.clone() }.worth_logging(&last, 5f64));
+    }
+}
@@ -1,21 +1,3 @@
-
-#[allow(dead_code)]
-pub fn average<'a, T: 'a + num::Float, I: IntoIterator<Item=&'a T>>(buffer: I) -> T {
-
-    let f = |acc: (T, usize), (idx, val): (usize, &T)| -> (T, usize) {
-        let total_weight = acc.1;
-        let sum = acc.0;
-
-        (sum + *val, std::cmp::max(idx + 1, total_weight))
-    };
-
-    let (sum, total_weight): (T, usize) = buffer.into_iter()
-        .enumerate()
-        .fold((T::zero(), 0), f);
-
-    sum / T::from(total_weight).unwrap()
-}
-
 pub fn index_weighted_average<'a, 
     T: 'a + num::Float,
     I: DoubleEndedIterator<Item=&'a T>>(it: I) -> T {
@@ -38,6 +20,18 @@ pub fn index_weighted_average<'a,
     sum / T::from(total_weight).unwrap()
 }
 
+/// Plain average of the newest count values, None without any
+pub fn tail_average<'a, T: 'a + num::Float, I: DoubleEndedIterator<Item=&'a T>>(it: I, count: usize) -> Option<T> {
+    let (sum, len) = it.rev()
+        .take(count)
+        .fold((T::zero(), 0usize), |(sum, len), val| (sum + *val, len + 1));
+
+    if len == 0 {
+        None
+    } else {
+        Some(sum / T::from(len).unwrap())
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -54,7 +48,7 @@ mod tests {
         buffer.add(4);
         buffer.add(5);
 
-        let expected: f64 = f64::from(5*5 + 4*4 + 3*3 + 2*2 + 1*1) / f64::from(5+4+3+2+1);
+        let expected: f64 = f64::from(5*5 + 4*4 + 3*3 + 2*2 + 1) / f64::from(5+4+3+2+1);
         assert_eq!(index_weighted_average(buffer.iter()), expected);
     }
 
@@ -72,45 +66,16 @@ mod tests {
     }
 
     #[test]
-    fn average_non_full() {
-        let mut buffer = CircularBuffer::<f64>::new(5);
-
-        buffer.add(1);
-        buffer.add(2);
-        buffer.add(3);
-
-        assert_eq!(average(&buffer), 2f64);
-    }
-
-    #[test]
-    fn average_full() {
+    fn tail_average_of_newest() {
         let mut buffer = CircularBuffer::<f64>::new(5);
 
-        buffer.add(1);
-        buffer.add(2);
-        buffer.add(3);
-        buffer.add(4);
-        buffer.add(5);
-
-        assert_eq!(average(&buffer), 3f64);
-    }
-
-    #[test]
-    fn average_overflown() {
-        let mut buffer = CircularBuffer::<f64>::new(5);
+        assert_eq!(tail_average(buffer.iter(), 2), None);
 
-        // These will be forgotten
         buffer.add(1);
         buffer.add(2);
-        buffer.add(3);
-
-        // These are the last
-        buffer.add(4);
-        buffer.add(5);
         buffer.add(6);
-        buffer.add(7);
-        buffer.add(8);
 
-        assert_eq!(average(&buffer), 6f64);
+        assert_eq!(tail_average(buffer.iter(), 2), Some(4f64));
+        assert_eq!(tail_average(buffer.iter(), 10), Some(3f64));
     }
 }
\ No newline at end of file
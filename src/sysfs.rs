@@ -1,10 +1,118 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::Path;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
 
 const DEBUG: bool = false;
 
+enum AuditSink {
+    Stdout,
+    File(File)
+}
+
+/// Receives every write when auditing is enabled
+static AUDIT: Mutex<Option<AuditSink>> = Mutex::new(None);
+
+/// Records every following write, to the file when given and stdout otherwise
+pub fn enable_audit<P: AsRef<Path>>(file: Option<P>) -> Result<(), std::io::Error> {
+    let sink = match file {
+        Some(path) => AuditSink::File(std::fs::OpenOptions::new().create(true).append(true).open(path)?),
+        None => AuditSink::Stdout
+    };
+    *AUDIT.lock().expect("Audit lock poisoned") = Some(sink);
+    Ok(())
+}
+
+/// Adds a line to the audit log, does nothing unless it's enabled
+pub fn audit(message: &str) {
+    match AUDIT.lock().expect("Audit lock poisoned").as_mut() {
+        Some(AuditSink::Stdout) => println!("  audit: {}", message),
+        Some(AuditSink::File(file)) => {
+            if let Err(err) = writeln!(file, "{}", message) {
+                println!("! Failed to write the audit log: {}", err);
+            }
+        },
+        None => {}
+    }
+}
+
+/// Whether a write may wait for the write budget to refill
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePriority {
+    /// Fan adjustments which the next step repeats anyway
+    Deferrable,
+    /// Everything else, these always go through
+    Critical
+}
+
+/// Token bucket refilling per_minute tokens a minute, holding up to a minute worth
+struct WriteBudget {
+    per_minute: f64,
+    tokens: f64,
+    refilled: Instant
+}
+
+impl WriteBudget {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_minute / 60f64).min(self.per_minute);
+        self.refilled = now;
+    }
+}
+
+static WRITE_BUDGET: Mutex<Option<WriteBudget>> = Mutex::new(None);
+
+/// Caps writes to per_minute, past which Deferrable writes are held back
+pub fn limit_writes(per_minute: u32) {
+    *WRITE_BUDGET.lock().expect("Write budget lock poisoned") = Some(WriteBudget {
+        per_minute: per_minute as f64,
+        tokens: per_minute as f64,
+        refilled: Instant::now()
+    });
+}
+
+/// Whether a write of the given priority may happen now, always true without a limit
+pub fn write_permitted(priority: WritePriority) -> bool {
+    match WRITE_BUDGET.lock().expect("Write budget lock poisoned").as_mut() {
+        Some(budget) if priority == WritePriority::Deferrable => {
+            budget.refill(Instant::now());
+            budget.tokens >= 1f64
+        },
+        _ => true
+    }
+}
+
+/// Critical writes still spend tokens, so they crowd out Deferrable ones
+fn spend_write() {
+    if let Some(budget) = WRITE_BUDGET.lock().expect("Write budget lock poisoned").as_mut() {
+        budget.refill(Instant::now());
+        budget.tokens = (budget.tokens - 1f64).max(0f64);
+    }
+}
+
+fn audit_write(path: &Path, value: &str, result: &Result<(), std::io::Error>) {
+    match result {
+        Ok(_) => audit(&format!("{} <- {}", path.display(), value)),
+        Err(err) => audit(&format!("{} <- {} failed: {}", path.display(), value, err))
+    }
+}
+
+/// Whether the file can be opened for writing, nothing is written
+pub fn is_writable<P: AsRef<Path>>(path: P) -> bool {
+    std::fs::OpenOptions::new().write(true).open(path).is_ok()
+}
+
 pub fn try_write<P: AsRef<Path>>(path: P, value: &'_ str) -> Result<(), std::io::Error> {
+    spend_write();
+    let result = write_value(path.as_ref(), value);
+    audit_write(path.as_ref(), value, &result);
+    result
+}
+
+fn write_value(path: &Path, value: &'_ str) -> Result<(), std::io::Error> {
 
     let value_with_newline = format!("{}\n", value);
 
@@ -12,7 +120,7 @@ pub fn try_write<P: AsRef<Path>>(path: P, value: &'_ str) -> Result<(), std::io:
         .write(true)
         .truncate(true)
         .create_new(false)
-        .open(path.as_ref())
+        .open(path)
     {
         Ok(mut file) => {
             match file.write_all(value_with_newline.as_bytes()) {
@@ -24,6 +132,30 @@ pub fn try_write<P: AsRef<Path>>(path: P, value: &'_ str) -> Result<(), std::io:
     }
 }
 
+/**
+    Writes every line through a single open file, each with its own write call
+    so the driver sees them as separate commands.
+**/
+pub fn write_lines<P: AsRef<Path>>(path: P, lines: &[&str]) -> Result<(), std::io::Error> {
+
+    if DEBUG {
+        println!("Writing: {:?} -> {}", lines, path.as_ref().display());
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create_new(false)
+        .open(path.as_ref())?;
+
+    for line in lines {
+        spend_write();
+        let result = file.write_all(format!("{}\n", line).as_bytes());
+        audit_write(path.as_ref(), line, &result);
+        result?;
+    }
+    file.sync_all()
+}
 
 pub fn write<P: AsRef<Path>>(path: P, value: &'_ str) {
 
@@ -45,6 +177,10 @@ pub fn parse_string_from_file<T: std::str::FromStr, P: AsRef<Path>>(path: &P) ->
     }
 }
 
+pub fn try_parse_string_from_file<T: std::str::FromStr, P: AsRef<Path>>(path: &P) -> Option<T> {
+    try_read_string_from_file(path).and_then(|data| data.trim().parse::<T>().ok())
+}
+
 pub fn try_read_string_from_file<P: AsRef<Path>>(path: &P) -> Option<String> {
     let mut data = String::new();
     File::open(path)
@@ -52,6 +188,40 @@ pub fn try_read_string_from_file<P: AsRef<Path>>(path: &P) -> Option<String> {
         .map_or(None, |_| Some(data))
 }
 
+/**
+    Keeps every file it read open and seeks back to the start for the next
+    read, sysfs generates the value again on each read from offset 0. Saves
+    an open per read and the stale first read some nodes give when freshly opened.
+**/
+#[derive(Debug, Default)]
+pub struct SysfsReader {
+    files: Mutex<HashMap<PathBuf, File>>
+}
+
+impl SysfsReader {
+    pub fn try_read_string(&self, path: &Path) -> Option<String> {
+        let mut files = self.files.lock().expect("Sysfs reader lock poisoned");
+        if !files.contains_key(path) {
+            files.insert(path.to_path_buf(), File::open(path).ok()?);
+        }
+
+        let file = files.get_mut(path)?;
+        let mut data = String::new();
+        match file.seek(SeekFrom::Start(0)).and_then(|_| file.read_to_string(&mut data)) {
+            Ok(_) => Some(data),
+            Err(_) => {
+                // Reopened on the next read, the node may have come back after a driver reload
+                files.remove(path);
+                None
+            }
+        }
+    }
+
+    pub fn try_parse<T: std::str::FromStr>(&self, path: &Path) -> Option<T> {
+        self.try_read_string(path).and_then(|data| data.trim().parse::<T>().ok())
+    }
+}
+
 pub fn read_string_from_file<P: AsRef<Path>>(path: &P) -> String {
     let mut file = File::open(path).expect("Could not open file");
     let mut data = String::new();
@@ -59,3 +229,67 @@ pub fn read_string_from_file<P: AsRef<Path>>(path: &P) -> String {
 
     data
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    #[test]
+    fn kept_open_file_reads_new_values() {
+        let path = std::env::temp_dir().join(format!("sentinel-reader-{}", std::process::id()));
+        std::fs::write(&path, "3\n").unwrap();
+
+        let reader = SysfsReader::default();
+        assert_eq!(reader.try_parse::<u32>(&path), Some(3));
+        std::fs::write(&path, "42\n").unwrap();
+        assert_eq!(reader.try_parse::<u32>(&path), Some(42));
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(SysfsReader::default().try_parse::<u32>(&path), None);
+    }
+
+    #[test]
+    fn checks_writability_without_writing() {
+        let path = std::env::temp_dir().join(format!("sentinel-writable-{}", std::process::id()));
+        std::fs::write(&path, "3\n").unwrap();
+
+        assert!(is_writable(&path));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "3\n");
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!is_writable(&path));
+    }
+
+    /// cargo test -- --ignored --nocapture compares the two, run it against a real node for meaningful numbers
+    #[test]
+    #[ignore]
+    fn benchmark_open_per_read_against_kept_open() {
+        let path = std::env::var("SENTINEL_BENCH_NODE").map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::temp_dir().join(format!("sentinel-bench-{}", std::process::id())));
+        let created = !path.exists();
+        if created {
+            std::fs::write(&path, "3\n").unwrap();
+        }
+        const READS: u32 = 10_000;
+
+        let start = Instant::now();
+        for _ in 0..READS {
+            try_read_string_from_file(&path).unwrap();
+        }
+        let per_open = start.elapsed() / READS;
+
+        let reader = SysfsReader::default();
+        let start = Instant::now();
+        for _ in 0..READS {
+            reader.try_read_string(&path).unwrap();
+        }
+        let kept_open = start.elapsed() / READS;
+
+        println!("{}: {:?} per read opening the file, {:?} kept open", path.display(), per_open, kept_open);
+        if created {
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
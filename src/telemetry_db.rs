@@ -0,0 +1,105 @@
+use crate::snapshot::GpuSnapshot;
+
+use rusqlite::{params, Connection};
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS samples (
+    timestamp REAL NOT NULL,
+    temperature REAL,
+    usage REAL,
+    power REAL,
+    fan_speed REAL,
+    fan_rpm INTEGER,
+    state TEXT NOT NULL,
+    core_clock INTEGER,
+    memory_clock INTEGER
+)";
+
+/**
+    Records a row per update into an SQLite database.
+    Rows are inserted inside a transaction which is only committed every
+    `flush_every` rows, a commit per row would fsync on every update.
+**/
+pub struct TelemetryDb {
+    connection: Connection,
+    pending: usize,
+    flush_every: usize
+}
+
+impl TelemetryDb {
+    pub fn open<P: AsRef<Path>>(path: P, flush_every: usize) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        connection.execute_batch("BEGIN")?;
+
+        Ok(TelemetryDb { connection, pending: 0, flush_every })
+    }
+
+    pub fn insert(&mut self, snapshot: &GpuSnapshot) -> rusqlite::Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_or(0f64, |elapsed| elapsed.as_secs_f64());
+
+        self.connection.prepare_cached("INSERT INTO samples VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)")?
+            .execute(params![
+                timestamp,
+                snapshot.temperature,
+                snapshot.usage,
+                snapshot.power_usage,
+                snapshot.fan_speed.map(|speed| speed.0),
+                snapshot.fan_rpm,
+                format!("{:?}", snapshot.state),
+                snapshot.core_clock,
+                snapshot.memory_clock
+            ])?;
+
+        self.pending += 1;
+        if self.pending >= self.flush_every {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> rusqlite::Result<()> {
+        self.connection.execute_batch("COMMIT; BEGIN")?;
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+impl Drop for TelemetryDb {
+    fn drop(&mut self) {
+        if let Err(err) = self.connection.execute_batch("COMMIT") {
+            println!("! Failed to commit telemetry: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GpuCustomState;
+    use crate::snapshot::snapshot;
+
+    #[test]
+    fn inserts_rows_in_batches() {
+        let path = std::env::temp_dir().join(format!("sentinel-telemetry-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let snapshot = GpuSnapshot { state: GpuCustomState::Idle, ..snapshot() };
+
+        {
+            let mut db = TelemetryDb::open(&path, 2).unwrap();
+            for _ in 0..3 {
+                db.insert(&snapshot).unwrap();
+            }
+        }
+
+        let connection = Connection::open(&path).unwrap();
+        let rows: i64 = connection.query_row("SELECT COUNT(*) FROM samples WHERE state = 'Idle'", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 3);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
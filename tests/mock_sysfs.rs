@@ -0,0 +1,291 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+const SENTINEL: &str = env!("CARGO_BIN_EXE_amdgpu-sentinel");
+
+fn write(path: PathBuf, data: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, data).unwrap();
+}
+
+/// Mimics the parts of /sys an RX 570 exposes
+fn create_sysfs_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("sentinel-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    let device = root.join("class/drm/card0/device");
+    write(device.join("uevent"), "DRIVER=amdgpu\nPCI_ID=1002:67DF\nPCI_SUBSYS_ID=1DA2:E366\n");
+    write(device.join("gpu_busy_percent"), "3\n");
+    write(device.join("power_dpm_force_performance_level"), "auto\n");
+    write(device.join("pp_dpm_sclk"), "0: 300Mhz *\n1: 1000Mhz\n");
+    write(device.join("pp_dpm_mclk"), "0: 300Mhz\n1: 1750Mhz *\n");
+    write(device.join("pp_power_profile_mode"), "NUM        MODE_NAME\n  0   BOOTUP_DEFAULT:\n  1 3D_FULL_SCREEN *:\n  2     POWER_SAVING:\n  5          COMPUTE:\n");
+    write(device.join("pp_od_clk_voltage"), "OD_SCLK:\n\
+        0: 300MHz 750mV\n\
+        1: 1000MHz 900mV\n\
+        2: 1250MHz 1025mV\n\
+        OD_MCLK:\n\
+        0: 300MHz 750mV\n\
+        1: 1750MHz 900mV\n\
+        OD_RANGE:\n\
+        SCLK: 300MHz 2000MHz\n\
+        MCLK: 300MHz 2250MHz\n\
+        VDDC: 750mV 1150mV\n");
+    write(device.join("driver/module/parameters/ppfeaturemask"), "0xfffd7fff\n");
+
+    let hwmon = device.join("hwmon/hwmon0");
+    write(hwmon.join("name"), "amdgpu\n");
+    write(hwmon.join("temp1_input"), "45000\n");
+    write(hwmon.join("pwm1"), "0\n");
+    write(hwmon.join("pwm1_enable"), "2\n");
+    write(hwmon.join("fan1_input"), "0\n");
+    write(hwmon.join("power1_average"), "20000000\n");
+    write(hwmon.join("power1_cap"), "150000000\n");
+    write(hwmon.join("power1_cap_min"), "0\n");
+    write(hwmon.join("power1_cap_max"), "200000000\n");
+
+    root
+}
+
+fn read(root: &Path, file: &str) -> String {
+    fs::read_to_string(root.join("class/drm/card0/device").join(file)).unwrap()
+}
+
+#[test]
+fn reports_capabilities_of_mock_card() {
+    let root = create_sysfs_root("caps");
+
+    let output = Command::new(SENTINEL).arg("--sysfs-root").arg(&root).arg("--caps").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Sapphire Radeon RX 470/480/570/580/590"), "{}", stdout);
+    assert!(stdout.contains("Overdrive:       yes"), "{}", stdout);
+    assert!(stdout.contains("Junction sensor: no"), "{}", stdout);
+    assert!(stdout.contains("Core:   0, 150, 275"), "{}", stdout);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn resolves_card_from_render_node() {
+    let root = create_sysfs_root("render-node");
+    fs::create_dir_all(root.join("class/drm/renderD128")).unwrap();
+    std::os::unix::fs::symlink(root.join("class/drm/card0/device"), root.join("class/drm/renderD128/device")).unwrap();
+
+    let command = |render_node: &str| Command::new(SENTINEL)
+        .arg("--sysfs-root").arg(&root)
+        .arg("--render-node").arg(render_node)
+        .arg("--caps")
+        .output().unwrap();
+
+    let output = command("/dev/dri/renderD128");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Overdrive:       yes"));
+    let output = command("/dev/dri/renderD129");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No card found behind the render node"));
+
+    write(root.join("dev/dri/renderD128"), "");
+    fs::create_dir_all(root.join("dev/dri/by-path")).unwrap();
+    let by_path = root.join("dev/dri/by-path/pci-0000:01:00.0-render");
+    std::os::unix::fs::symlink("../renderD128", &by_path).unwrap();
+    assert!(command(by_path.to_str().unwrap()).status.success());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn saves_and_restores_card_state() {
+    let root = create_sysfs_root("card-state");
+    let saved = root.join("card.json");
+
+    let run = |flag: &str| Command::new(SENTINEL).arg("--sysfs-root").arg(&root).arg(flag).arg(&saved).output().unwrap();
+
+    // The profile only sticks while the performance level is manual
+    write(root.join("class/drm/card0/device/power_dpm_force_performance_level"), "manual\n");
+    let output = run("--save-state");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    let state = fs::read_to_string(&saved).unwrap();
+    assert!(state.contains("\"power_limit\": 150.0") && state.contains("\"fan_mode\": \"Auto\""), "{}", state);
+    assert!(state.contains("\"power_profile_mode\": 1"), "{}", state);
+
+    // The mock can't take pstate commands, so the table is left out of the restore
+    let mut state: serde_json::Value = serde_json::from_str(&state).unwrap();
+    state["pstate_table"] = serde_json::Value::Null;
+    fs::write(&saved, state.to_string()).unwrap();
+    write(root.join("class/drm/card0/device/hwmon/hwmon0/power1_cap"), "90000000\n");
+    write(root.join("class/drm/card0/device/power_dpm_force_performance_level"), "auto\n");
+
+    let output = run("--restore-state");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert_eq!(read(&root, "hwmon/hwmon0/power1_cap"), "150000000\n");
+    assert_eq!(read(&root, "power_dpm_force_performance_level").trim(), "manual");
+    assert_eq!(read(&root, "pp_power_profile_mode").trim(), "1");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn read_only_loop_leaves_mock_card_untouched() {
+    let root = create_sysfs_root("read-only");
+    let table = read(&root, "pp_od_clk_voltage");
+
+    let child = Command::new(SENTINEL)
+        .arg("--sysfs-root").arg(&root)
+        .arg("--read-only")
+        .stdout(Stdio::piped())
+        .spawn().unwrap();
+
+    thread::sleep(Duration::from_millis(1500));
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("temperature: 45C"), "{}", stdout);
+    assert_eq!(read(&root, "pp_od_clk_voltage"), table);
+    assert_eq!(read(&root, "hwmon/hwmon0/pwm1_enable"), "2\n");
+    assert_eq!(read(&root, "hwmon/hwmon0/power1_cap"), "150000000\n");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn read_only_runs_with_overdrive_disabled() {
+    let root = create_sysfs_root("no-overdrive");
+    fs::remove_file(root.join("class/drm/card0/device/pp_od_clk_voltage")).unwrap();
+    write(root.join("class/drm/card0/device/driver/module/parameters/ppfeaturemask"), "0xffffbfff\n");
+
+    let child = Command::new(SENTINEL)
+        .arg("--sysfs-root").arg(&root)
+        .arg("--read-only")
+        .stdout(Stdio::piped())
+        .spawn().unwrap();
+
+    thread::sleep(Duration::from_millis(1500));
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("disabling pstate table") && stdout.contains("Overdrive is disabled"), "{}", stdout);
+    assert!(stdout.contains("temperature: 45C"), "{}", stdout);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+/// Runs the control loop on the mock card with the config, returns its stdout
+fn run_briefly(root: &Path, config: &str) -> String {
+    write(root.join("config.toml"), &format!("audit = true\nreset_on_startup = false\n{}", config));
+
+    let child = Command::new(SENTINEL)
+        .arg("--sysfs-root").arg(root)
+        .arg("--config").arg(root.join("config.toml"))
+        .stdout(Stdio::piped())
+        .spawn().unwrap();
+
+    thread::sleep(Duration::from_millis(1500));
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+
+    assert!(output.status.success(), "{}", stdout);
+    stdout
+}
+
+#[test]
+fn skips_power_profile_without_manual_level() {
+    let root = create_sysfs_root("profile-level");
+    let stdout = run_briefly(&root, "idle_performance_level = \"low\"\n");
+    assert!(stdout.contains("power_dpm_force_performance_level <- low"), "{}", stdout);
+    assert!(!stdout.contains("pp_power_profile_mode <- 2"), "{}", stdout);
+    fs::remove_dir_all(&root).unwrap();
+
+    let root = create_sysfs_root("profile-schedule");
+    let stdout = run_briefly(&root, "performance_level_schedule = [\
+        { start = \"00:00\", end = \"12:00\", performance_level = \"low\" },\
+        { start = \"12:00\", end = \"00:00\", performance_level = \"low\" }]\n");
+    assert!(stdout.contains("power_dpm_force_performance_level <- low"), "{}", stdout);
+    assert!(!stdout.contains("pp_power_profile_mode <- 2"), "{}", stdout);
+    fs::remove_dir_all(&root).unwrap();
+
+    let root = create_sysfs_root("profile-manual");
+    let stdout = run_briefly(&root, "");
+    assert!(stdout.contains("pp_power_profile_mode <- 2"), "{}", stdout);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn shutdown_restores_past_a_failed_step() {
+    let root = create_sysfs_root("shutdown");
+    // A directory can't be written to, so the profile write fails
+    let profile = root.join("class/drm/card0/device/pp_power_profile_mode");
+    fs::remove_file(&profile).unwrap();
+    fs::create_dir(&profile).unwrap();
+
+    let stdout = run_briefly(&root, "");
+    let shutdown = &stdout[stdout.find("Shutting down").expect("No shutdown in the audit log")..];
+    assert!(shutdown.contains("Failed to restore the default power profile"), "{}", shutdown);
+    assert!(shutdown.contains("power_dpm_force_performance_level <- auto"), "{}", shutdown);
+    assert!(shutdown.contains("pp_od_clk_voltage <- r"), "{}", shutdown);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn refuses_relative_value_out_of_range() {
+    let root = create_sysfs_root("relative");
+    // The mock reports no temp1_crit, so crit can't be resolved
+    write(root.join("config.toml"), "emergency_temperature = \"95% crit\"\n");
+
+    let output = Command::new(SENTINEL)
+        .arg("--sysfs-root").arg(&root)
+        .arg("--config").arg(root.join("config.toml"))
+        .arg("--read-only")
+        .output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("emergency_temperature is relative to Crit") && !stderr.contains("panicked"), "{}", stderr);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn dumps_unparseable_table() {
+    let root = create_sysfs_root("unparsed");
+    write(root.join("class/drm/card0/device/pp_od_clk_voltage"), "GFXCLK:\n0: 300MHz\n");
+    let dump = root.join("table.txt");
+    write(root.join("config.toml"), &format!("unparsed_table_dump = {:?}\n", dump));
+
+    let output = Command::new(SENTINEL)
+        .arg("--sysfs-root").arg(&root)
+        .arg("--config").arg(root.join("config.toml"))
+        .arg("--read-only")
+        .output().unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(fs::read_to_string(&dump).unwrap(), "GFXCLK:\n0: 300MHz\n");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn refuses_card_missing_required_nodes() {
+    let root = create_sysfs_root("missing");
+    fs::remove_file(root.join("class/drm/card0/device/gpu_busy_percent")).unwrap();
+
+    let output = Command::new(SENTINEL).arg("--sysfs-root").arg(&root).arg("--read-only").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stderr.contains("Preflight checks failed") && stderr.contains("gpu_busy_percent\" is missing"), "{}", stderr);
+    assert!(stdout.contains("disabling junction temperature"), "{}", stdout);
+
+    fs::remove_dir_all(&root).unwrap();
+}